@@ -21,4 +21,30 @@ pub enum CustomError {
   MaxStaked,
   #[msg("Already Boosted")]
   AlreadyBoosted,
+  #[msg("Vault is paused")]
+  Paused,
+  #[msg("Vault is not paused")]
+  NotPaused,
+  #[msg("No authority handover is pending")]
+  NoPendingAuthority,
+  #[msg("No settings change is queued")]
+  NoPendingUpdate,
+  #[msg("Queued settings change is not effective yet")]
+  UpdateNotReady,
+  #[msg("Vault still has staked NFTs or reward tokens")]
+  VaultNotEmpty,
+  #[msg("Stake account still has staked NFTs or pending rewards")]
+  StakeAccountNotEmpty,
+  #[msg("Unknown whitelist mode")]
+  InvalidWhitelistMode,
+  #[msg("Whitelist is full")]
+  WhitelistFull,
+  #[msg("Whitelist entry already exists")]
+  DuplicateWhitelistEntry,
+  #[msg("Whitelist entry not found")]
+  WhitelistEntryNotFound,
+  #[msg("Rarity proof does not match the vault's rarity root")]
+  InvalidRarityProof,
+  #[msg("Vault already uses the current layout")]
+  AlreadyMigrated,
 }
@@ -0,0 +1,236 @@
+use anchor_lang::prelude::*;
+
+// Program Events
+#[event]
+pub struct VaultInitialized {
+  pub vault: Pubkey,
+  pub authority: Pubkey,
+  pub creator_address: Pubkey,
+  pub community_wallet: Pubkey,
+  pub payout_interval: u64,
+  pub payout_amount: u64,
+  pub stake_fee: u64,
+  pub unstake_fee: u64,
+  pub timestamp: i64,
+}
+
+#[event]
+pub struct VaultUpdated {
+  pub vault: Pubkey,
+  pub creator_address: Pubkey,
+  pub collection_address: Pubkey,
+  pub whitelist_mode: u8,
+  pub timestamp: i64,
+}
+
+#[event]
+pub struct VaultMigrated {
+  pub vault: Pubkey,
+  pub authority: Pubkey,
+  pub timestamp: i64,
+}
+
+#[event]
+pub struct WhitelistEntryAdded {
+  pub vault: Pubkey,
+  pub kind: u8,
+  pub address: Pubkey,
+  pub reward_rate: u64,
+  pub entry_count: u8,
+  pub timestamp: i64,
+}
+
+#[event]
+pub struct WhitelistEntryRemoved {
+  pub vault: Pubkey,
+  pub kind: u8,
+  pub address: Pubkey,
+  pub entry_count: u8,
+  pub timestamp: i64,
+}
+
+#[event]
+pub struct WhitelistMintRootSet {
+  pub vault: Pubkey,
+  pub mint_root: [u8; 32],
+  pub mint_reward_rate: u64,
+  pub timestamp: i64,
+}
+
+#[event]
+pub struct RarityRootSet {
+  pub vault: Pubkey,
+  pub rarity_root: [u8; 32],
+  pub timestamp: i64,
+}
+
+#[event]
+pub struct UpdateQueued {
+  pub vault: Pubkey,
+  pub community_wallet: Pubkey,
+  pub stake_fee: u64,
+  pub unstake_fee: u64,
+  pub payout_interval: u64,
+  pub payout_amount: u64,
+  pub update_delay: u64,
+  pub effective_at: u64,
+  pub timestamp: i64,
+}
+
+#[event]
+pub struct UpdateExecuted {
+  pub vault: Pubkey,
+  pub community_wallet: Pubkey,
+  pub stake_fee: u64,
+  pub unstake_fee: u64,
+  pub payout_interval: u64,
+  pub payout_amount: u64,
+  pub update_delay: u64,
+  pub timestamp: i64,
+}
+
+#[event]
+pub struct UpdateCancelled {
+  pub vault: Pubkey,
+  pub timestamp: i64,
+}
+
+#[event]
+pub struct RolesUpdated {
+  pub vault: Pubkey,
+  pub fee_manager: Pubkey,
+  pub rewards_manager: Pubkey,
+  pub pauser: Pubkey,
+  pub timestamp: i64,
+}
+
+#[event]
+pub struct AuthorityChanged {
+  pub vault: Pubkey,
+  pub old_authority: Pubkey,
+  pub new_authority: Pubkey,
+  pub timestamp: i64,
+}
+
+#[event]
+pub struct AuthorityProposed {
+  pub vault: Pubkey,
+  pub authority: Pubkey,
+  pub pending_authority: Pubkey,
+  pub timestamp: i64,
+}
+
+#[event]
+pub struct AuthorityProposalCancelled {
+  pub vault: Pubkey,
+  pub authority: Pubkey,
+  pub pending_authority: Pubkey,
+  pub timestamp: i64,
+}
+
+#[event]
+pub struct VaultPaused {
+  pub vault: Pubkey,
+  pub paused: bool,
+  pub timestamp: i64,
+}
+
+#[event]
+pub struct VaultClosed {
+  pub vault: Pubkey,
+  pub authority: Pubkey,
+  pub recipient: Pubkey,
+  pub timestamp: i64,
+}
+
+#[event]
+pub struct Funded {
+  pub vault: Pubkey,
+  pub funder: Pubkey,
+  pub mint: Pubkey,
+  pub amount: u64,
+  pub total_amount: u64,
+  pub timestamp: i64,
+}
+
+#[event]
+pub struct Drained {
+  pub vault: Pubkey,
+  pub authority: Pubkey,
+  pub mint: Pubkey,
+  pub amount: u64,
+  pub total_amount: u64,
+  pub timestamp: i64,
+}
+
+#[event]
+pub struct StakeAccountCreated {
+  pub user: Pubkey,
+  pub user_account: Pubkey,
+  pub timestamp: i64,
+}
+
+#[event]
+pub struct StakeAccountClosed {
+  pub user: Pubkey,
+  pub user_account: Pubkey,
+  pub timestamp: i64,
+}
+
+#[event]
+pub struct StakeAccountMigrated {
+  pub user: Pubkey,
+  pub user_account: Pubkey,
+  pub vault: Pubkey,
+  pub timestamp: i64,
+}
+
+#[event]
+pub struct Staked {
+  pub vault: Pubkey,
+  pub staker: Pubkey,
+  pub mint: Pubkey,
+  pub reward_rate: u64,
+  pub stake_fee: u64,
+  pub reward_earned_pending: u64,
+  pub mint_staked_count: u32,
+  pub total_reward_rate: u64,
+  pub total_staked: u32,
+  pub timestamp: i64,
+}
+
+#[event]
+pub struct Unstaked {
+  pub vault: Pubkey,
+  pub staker: Pubkey,
+  pub signer: Pubkey,
+  pub mint: Pubkey,
+  pub unstake_fee: u64,
+  pub reward_earned_pending: u64,
+  pub mint_staked_count: u32,
+  pub total_reward_rate: u64,
+  pub total_staked: u32,
+  pub timestamp: i64,
+}
+
+#[event]
+pub struct EmergencyUnstaked {
+  pub vault: Pubkey,
+  pub staker: Pubkey,
+  pub mint: Pubkey,
+  pub mint_staked_count: u32,
+  pub total_reward_rate: u64,
+  pub total_staked: u32,
+  pub timestamp: i64,
+}
+
+#[event]
+pub struct Claimed {
+  pub vault: Pubkey,
+  pub staker: Pubkey,
+  pub mint: Pubkey,
+  pub amount: u64,
+  pub reward_earned_claimed: u64,
+  pub total_amount: u64,
+  pub timestamp: i64,
+}
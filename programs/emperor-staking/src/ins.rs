@@ -24,6 +24,18 @@ pub struct InitializeVault<'info> {
     )]
     pub vault: Account<'info, Vault>,
 
+    #[account(
+        init,
+        space = Whitelist::LEN + 8,
+        seeds = [
+            b"whitelist".as_ref(),
+            vault.key().as_ref(),
+        ],
+        bump,
+        payer = authority,
+    )]
+    pub whitelist: Box<Account<'info, Whitelist>>,
+
     pub system_program: Program<'info, System>,
 }
 
@@ -43,6 +55,67 @@ pub struct UpdateVault<'info> {
     pub vault: Account<'info, Vault>,
 }
 
+#[derive(Accounts)]
+pub struct UpdateWhitelist<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [
+            b"vault".as_ref(),
+            vault.name.as_ref(),
+        ],
+        bump = vault.bump
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"whitelist".as_ref(),
+            vault.key().as_ref(),
+        ],
+        bump = whitelist.bump
+    )]
+    pub whitelist: Box<Account<'info, Whitelist>>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateVault<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// CHECK: still in the legacy layout, checked and rewritten by the handler.
+    #[account(mut, owner = crate::ID)]
+    pub vault: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        space = Whitelist::LEN + 8,
+        seeds = [
+            b"whitelist".as_ref(),
+            vault.key().as_ref(),
+        ],
+        bump,
+        payer = authority,
+    )]
+    pub whitelist: Box<Account<'info, Whitelist>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ExecutePendingUpdate<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"vault".as_ref(),
+            vault.name.as_ref(),
+        ],
+        bump = vault.bump
+    )]
+    pub vault: Account<'info, Vault>,
+}
+
 #[derive(Accounts)]
 pub struct FundTokenVault<'info> {
     #[account(mut)]
@@ -141,6 +214,22 @@ pub struct SetVaultAuthority<'info> {
     pub vault: Account<'info, Vault>,
 }
 
+#[derive(Accounts)]
+pub struct AcceptAuthority<'info> {
+    #[account(mut)]
+    pub pending_authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"vault".as_ref(),
+            vault.name.as_ref(),
+        ],
+        bump = vault.bump
+    )]
+    pub vault: Account<'info, Vault>,
+}
+
 #[derive(Accounts)]
 pub struct CreateStakeAccount<'info> {
     #[account(mut)]
@@ -151,6 +240,30 @@ pub struct CreateStakeAccount<'info> {
     pub user_account: AccountLoader<'info, User>,
 }
 
+#[derive(Accounts)]
+pub struct MigrateStakeAccount<'info> {
+    #[account(mut)]
+    pub staker: Signer<'info>,
+
+    #[account(
+        seeds = [
+            b"vault".as_ref(),
+            vault.name.as_ref(),
+        ],
+        bump = vault.bump
+    )]
+    pub vault: Account<'info, Vault>,
+
+    /// CHECK: still in the legacy layout, checked and grown by the handler.
+    #[account(mut, owner = crate::ID)]
+    pub staker_account: UncheckedAccount<'info>,
+
+    /// CHECK: only read while NFTs are staked, checked against the first of them then.
+    pub staked_token_account: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
 #[derive(Accounts)]
 pub struct Stake<'info> {
     #[account(mut)]
@@ -169,6 +282,15 @@ pub struct Stake<'info> {
     )]
     pub vault: Account<'info, Vault>,
 
+    #[account(
+        seeds = [
+            b"whitelist".as_ref(),
+            vault.key().as_ref(),
+        ],
+        bump = whitelist.bump
+    )]
+    pub whitelist: Box<Account<'info, Whitelist>>,
+
     #[account(mut, address = vault.community_wallet)]
     pub community_wallet: SystemAccount<'info>,
 
@@ -249,6 +371,44 @@ pub struct Unstake<'info> {
     pub token_metadata_program: AccountInfo<'info>,
 }
 
+#[derive(Accounts)]
+pub struct EmergencyUnstake<'info> {
+    #[account(mut)]
+    pub staker: Signer<'info>,
+
+    #[account(mut)]
+    pub staker_account: AccountLoader<'info, User>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"vault".as_ref(),
+            vault.name.as_ref(),
+        ],
+        bump = vault.bump
+    )]
+    pub vault: Account<'info, Vault>,
+
+    pub token_mint: Account<'info, Mint>,
+
+    // The Token Account holding the NFT.
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = staker,
+    )]
+    pub staker_ata: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK:
+    pub edition: AccountInfo<'info>,
+
+    pub token_program: Program<'info, Token>,
+    // the token metadata program
+    /// CHECK:
+    #[account(constraint = token_metadata_program.key == &metaplex_token_metadata::ID)]
+    pub token_metadata_program: AccountInfo<'info>,
+}
+
 #[derive(Accounts)]
 pub struct Claim<'info> {
     #[account(mut)]
@@ -297,13 +457,68 @@ pub struct Claim<'info> {
 }
 
 #[derive(Accounts)]
-pub struct ClosePda<'info> {
-    #[account(mut, address = "3qWq2ehELrVJrTg2JKKERm67cN6vYjm1EyhCEzfQ6jMd".parse::<Pubkey>().unwrap())]
-    pub signer: Signer<'info>,
+pub struct CloseStakeAccount<'info> {
+    #[account(mut)]
+    pub staker: Signer<'info>,
 
-    /// CHECK:
+    #[account(mut, close = staker)]
+    pub staker_account: AccountLoader<'info, User>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"vault".as_ref(),
+            vault.name.as_ref(),
+        ],
+        bump = vault.bump
+    )]
+    pub vault: Account<'info, Vault>,
+
+    pub reward_token_mint: Account<'info, Mint>,
+
+    /// CHECK: only used when paying out, checked against the staker's ATA and created then.
+    #[account(mut)]
+    pub staker_ata: UncheckedAccount<'info>,
+
+    /// CHECK: only used when paying out, checked against the vault's ATA then.
     #[account(mut)]
-    pub pda: AccountInfo<'info>,
+    pub reward_token_vault_ata: UncheckedAccount<'info>,
 
     pub system_program: Program<'info, System>,
-}
\ No newline at end of file
+
+    pub token_program: Program<'info, Token>,
+
+    pub associated_token_program: Program<'info, AssociatedToken>,
+
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct CloseVault<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        close = recipient,
+        seeds = [
+            b"vault".as_ref(),
+            vault.name.as_ref(),
+        ],
+        bump = vault.bump
+    )]
+    pub vault: Account<'info, Vault>,
+
+    #[account(
+        mut,
+        close = recipient,
+        seeds = [
+            b"whitelist".as_ref(),
+            vault.key().as_ref(),
+        ],
+        bump = whitelist.bump
+    )]
+    pub whitelist: Box<Account<'info, Whitelist>>,
+
+    #[account(mut)]
+    pub recipient: SystemAccount<'info>,
+}
@@ -1,6 +1,8 @@
 mod ins;
 mod state;
 mod errors;
+mod events;
+mod merkle;
 mod user;
 mod stake;
 mod vault;
@@ -11,6 +13,7 @@ use crate::ins::*;
 use crate::vault::*;
 use crate::user::*;
 use crate::stake::*;
+use crate::state::RarityProof;
 
 declare_id!("DVTouieqqLknDQn2UPE87HvWVizMkVj1Q4rqDgjNFYpK");
 
@@ -43,25 +46,97 @@ pub mod emperor_staking {
     pub fn update_vault(
         ctx: Context<UpdateVault>,
         creator_address: Pubkey,
-        payout_interval: u64,
-        payout_amount: u64,
+        collection_address: Pubkey,
+        whitelist_mode: u8,
+    ) -> Result<()> {
+        handle_vault_update(ctx, creator_address, collection_address, whitelist_mode)
+    }
+
+    pub fn update_fees(
+        ctx: Context<UpdateVault>,
         community_wallet: Pubkey,
         stake_fee: u64,
         unstake_fee: u64,
     ) -> Result<()> {
-        handle_vault_update(
-            ctx,
-            creator_address,
-            payout_interval,
-            payout_amount,
-            community_wallet,
-            stake_fee,
-            unstake_fee,
-        )
+        handle_fees_update(ctx, community_wallet, stake_fee, unstake_fee)
+    }
+
+    pub fn update_payout(
+        ctx: Context<UpdateVault>,
+        payout_interval: u64,
+        payout_amount: u64,
+    ) -> Result<()> {
+        handle_payout_update(ctx, payout_interval, payout_amount)
+    }
+
+    pub fn set_update_delay(ctx: Context<UpdateVault>, update_delay: u64) -> Result<()> {
+        handle_set_update_delay(ctx, update_delay)
+    }
+
+    pub fn migrate_vault(ctx: Context<MigrateVault>) -> Result<()> {
+        handle_migrate_vault(ctx)
+    }
+
+    pub fn set_rarity_root(ctx: Context<UpdateVault>, rarity_root: [u8; 32]) -> Result<()> {
+        handle_set_rarity_root(ctx, rarity_root)
+    }
+
+    pub fn add_whitelist_entry(
+        ctx: Context<UpdateWhitelist>,
+        kind: u8,
+        address: Pubkey,
+        reward_rate: u64,
+    ) -> Result<()> {
+        handle_add_whitelist_entry(ctx, kind, address, reward_rate)
+    }
+
+    pub fn remove_whitelist_entry(
+        ctx: Context<UpdateWhitelist>,
+        kind: u8,
+        address: Pubkey,
+    ) -> Result<()> {
+        handle_remove_whitelist_entry(ctx, kind, address)
+    }
+
+    pub fn set_whitelist_mint_root(
+        ctx: Context<UpdateWhitelist>,
+        mint_root: [u8; 32],
+        mint_reward_rate: u64,
+    ) -> Result<()> {
+        handle_set_whitelist_mint_root(ctx, mint_root, mint_reward_rate)
+    }
+
+    pub fn execute_pending_update(ctx: Context<ExecutePendingUpdate>) -> Result<()> {
+        handle_execute_pending_update(ctx)
+    }
+
+    pub fn cancel_pending_update(ctx: Context<UpdateVault>) -> Result<()> {
+        handle_cancel_pending_update(ctx)
+    }
+
+    pub fn set_roles(
+        ctx: Context<UpdateVault>,
+        fee_manager: Pubkey,
+        rewards_manager: Pubkey,
+        pauser: Pubkey,
+    ) -> Result<()> {
+        handle_set_roles(ctx, fee_manager, rewards_manager, pauser)
+    }
+
+    pub fn propose_authority(ctx: Context<SetVaultAuthority>) -> Result<()> {
+        handle_propose_authority(ctx)
+    }
+
+    pub fn cancel_authority(ctx: Context<UpdateVault>) -> Result<()> {
+        handle_cancel_authority(ctx)
+    }
+
+    pub fn accept_authority(ctx: Context<AcceptAuthority>) -> Result<()> {
+        handle_accept_authority(ctx)
     }
 
-    pub fn set_vault_authority(ctx: Context<SetVaultAuthority>) -> Result<()> {
-        handle_set_vault_authority(ctx)
+    pub fn set_paused(ctx: Context<UpdateVault>, paused: bool) -> Result<()> {
+        handle_set_paused(ctx, paused)
     }
 
     pub fn fund(ctx: Context<FundTokenVault>, amount: u64) -> Result<()> {
@@ -76,27 +151,35 @@ pub mod emperor_staking {
         handle_create_stake_account(ctx)
     }
 
-    pub fn stake(ctx: Context<Stake>) -> Result<()> {
-        handle_stake(ctx)
+    pub fn migrate_stake_account(ctx: Context<MigrateStakeAccount>) -> Result<()> {
+        handle_migrate_stake_account(ctx)
+    }
+
+    pub fn stake(
+        ctx: Context<Stake>,
+        mint_proof: Option<Vec<[u8; 32]>>,
+        rarity_proof: Option<RarityProof>,
+    ) -> Result<()> {
+        handle_stake(ctx, mint_proof, rarity_proof)
     }
 
     pub fn unstake(ctx: Context<Unstake>) -> Result<()> {
         handle_unstake(ctx)
     }
 
+    pub fn emergency_unstake(ctx: Context<EmergencyUnstake>) -> Result<()> {
+        handle_emergency_unstake(ctx)
+    }
+
     pub fn claim(ctx: Context<Claim>) -> Result<u64> {
         handle_claim_rewards(ctx)
     }
+
+    pub fn close_stake_account(ctx: Context<CloseStakeAccount>, payout: bool) -> Result<()> {
+        handle_close_stake_account(ctx, payout)
+    }
     
-    pub fn close_pda(ctx: Context<ClosePda>) -> Result<()> {
-        let dest_account_info = ctx.accounts.signer.to_account_info();
-        let source_account_info = ctx.accounts.pda.to_account_info();
-        let dest_starting_lamports = dest_account_info.lamports();
-        **dest_account_info.lamports.borrow_mut() = dest_starting_lamports
-            .checked_add(source_account_info.lamports())
-            .unwrap();
-        **source_account_info.lamports.borrow_mut() = 0;
-
-        Ok(())
+    pub fn close_vault(ctx: Context<CloseVault>) -> Result<()> {
+        handle_close_vault(ctx)
     }
 }
@@ -0,0 +1,17 @@
+use anchor_lang::solana_program::keccak::hashv;
+
+/*
+* Merkle:: Verify Proof - Hash `leaf` up through `proof`, sorting each pair so the proof
+* carries no left/right flags, and compare the result with `root`.
+*/
+pub fn verify(proof: &[[u8; 32]], root: [u8; 32], leaf: [u8; 32]) -> bool {
+  let mut computed_hash = leaf;
+  for node in proof {
+    computed_hash = if computed_hash <= *node {
+      hashv(&[&computed_hash, node]).0
+    } else {
+      hashv(&[node, &computed_hash]).0
+    };
+  }
+  computed_hash == root
+}
@@ -1,22 +1,43 @@
 use std::cell::RefMut;
 
 use anchor_lang::prelude::*;
-use anchor_lang::solana_program::clock;
+use anchor_lang::solana_program::{clock, keccak};
 use anchor_spl::token::Mint;
 use mpl_token_metadata::instruction::{freeze_delegated_account, thaw_delegated_account};
+use mpl_token_metadata::state::{Metadata, TokenMetadataAccount};
 use solana_program::program::{invoke, invoke_signed};
 
 use crate::errors::*;
+use crate::events::*;
+use crate::merkle;
 use crate::state::*;
 use crate::user::*;
 use crate::ins::*;
 /*
 * Stake:: Stake Instruction - Stake the user's NFT.
 */
-pub fn handle_stake(ctx: Context<Stake>) -> Result<()> {
+pub fn handle_stake(
+  ctx: Context<Stake>,
+  mint_proof: Option<Vec<[u8; 32]>>,
+  rarity_proof: Option<RarityProof>,
+) -> Result<()> {
   let vault = &mut ctx.accounts.vault;
   let staker_account = &mut ctx.accounts.staker_account.load_mut()?;
   let token_mint = &ctx.accounts.token_mint;
+  require_eq!(vault.paused, false, CustomError::Paused);
+
+  // Staker should own staker account
+  require_keys_eq!(
+    ctx.accounts.staker.key(),
+    staker_account.user.key(),
+    CustomError::KeyMismatch
+  );
+
+  // The first stake ties the account to this vault.
+  if staker_account.vault == Pubkey::default() {
+    staker_account.vault = vault.key();
+  }
+  require_keys_eq!(staker_account.vault, vault.key(), CustomError::IncorrectVault);
 
   let mut is_max_staked = false;
   if staker_account.mint_staked_count >= MAX_NFT_PER_USER as u32 {
@@ -24,19 +45,73 @@ pub fn handle_stake(ctx: Context<Stake>) -> Result<()> {
   }
   require_eq!(is_max_staked, false, CustomError::MaxStaked);
 
-  // Load the NFT metadata
-  let metadata =
-    spl_token_metadata::state::Metadata::from_account_info(&ctx.accounts.nft_metadata_account)?;
-  let creators = metadata.data.creators.unwrap();
-  let mut creator_found = false;
-  for creator in creators {
-    if creator.address.key() == vault.creator_address {
-      creator_found = true;
-    }
-  }
+  // Load the NFT metadata, owned by the token metadata program and describing this mint.
+  let metadata = Metadata::from_account_info(&ctx.accounts.nft_metadata_account)?;
+  require_keys_eq!(metadata.mint, token_mint.key(), CustomError::MintMismatch);
+
+  // Only verified entries count, anyone can list an unverified creator or collection.
+  let has_creator = |address: Pubkey| {
+    metadata
+      .data
+      .creators
+      .iter()
+      .flatten()
+      .any(|creator| creator.verified && creator.address == address)
+  };
+  let in_collection = |address: Pubkey| {
+    metadata
+      .collection
+      .iter()
+      .any(|collection| collection.verified && collection.key == address)
+  };
+
+  let creator_found = has_creator(vault.creator_address);
+  let collection_found = in_collection(vault.collection_address);
+  let vault_rule_matched = match vault.whitelist_mode {
+    WHITELIST_CREATOR => creator_found,
+    WHITELIST_COLLECTION => collection_found,
+    _ => creator_found && collection_found,
+  };
+
+  // A mint proof picks the mint list, otherwise the vault's own rule and then the first
+  // matching whitelist entry decide the rate.
+  let whitelist = &ctx.accounts.whitelist;
+  let default_reward_rate = if let Some(proof) = mint_proof {
+    let leaf = keccak::hash(token_mint.key().as_ref()).0;
+    require!(
+      whitelist.mint_root != [0; 32] && merkle::verify(&proof, whitelist.mint_root, leaf),
+      CustomError::WrongNFT
+    );
+    whitelist.mint_reward_rate
+  } else if vault_rule_matched {
+    vault.payout_amount
+  } else {
+    whitelist.entries[..whitelist.entry_count as usize]
+      .iter()
+      .find(|entry| match entry.kind {
+        WHITELIST_CREATOR => has_creator(entry.address),
+        _ => in_collection(entry.address),
+      })
+      .map(|entry| entry.reward_rate)
+      .ok_or(CustomError::WrongNFT)?
+  };
 
-  // NFT must be created by whitelist owner.
-  require_eq!(creator_found, true, CustomError::WrongNFT);
+  // A proven rarity rate replaces whatever rate the NFT was accepted at.
+  let reward_rate = match rarity_proof {
+    Some(rarity) => {
+      let leaf = keccak::hashv(&[
+        token_mint.key().as_ref(),
+        &rarity.reward_rate.to_le_bytes(),
+      ])
+      .0;
+      require!(
+        vault.rarity_root != [0; 32] && merkle::verify(&rarity.proof, vault.rarity_root, leaf),
+        CustomError::InvalidRarityProof
+      );
+      rarity.reward_rate
+    }
+    None => default_reward_rate,
+  };
   
   if vault.stake_fee > 0 {
     invoke(
@@ -54,7 +129,20 @@ pub fn handle_stake(ctx: Context<Stake>) -> Result<()> {
   }
   
 
-  update_accounts("stake", vault, staker_account, token_mint);
+  update_accounts("stake", vault, staker_account, token_mint, reward_rate);
+
+  emit!(Staked {
+    vault: vault.key(),
+    staker: staker_account.user,
+    mint: token_mint.key(),
+    reward_rate,
+    stake_fee: vault.stake_fee,
+    reward_earned_pending: staker_account.reward_earned_pending,
+    mint_staked_count: staker_account.mint_staked_count,
+    total_reward_rate: staker_account.total_reward_rate,
+    total_staked: vault.total_staked,
+    timestamp: staker_account.last_update_time as i64,
+  });
 
   let cpi_context = CpiContext::new(
     ctx.accounts.token_program.to_account_info(),
@@ -110,6 +198,7 @@ pub fn handle_unstake(ctx: Context<Unstake>) -> Result<()> {
     staker_account.user.key(),
     CustomError::KeyMismatch
   );
+  require_keys_eq!(staker_account.vault, vault.key(), CustomError::IncorrectVault);
 
   // If the staker key is not the same as the signer key,
   // then the signer account should match authority key.
@@ -153,7 +242,20 @@ pub fn handle_unstake(ctx: Context<Unstake>) -> Result<()> {
     )?;
   }
  
-  update_accounts("unstake", vault, staker_account, token_mint);
+  update_accounts("unstake", vault, staker_account, token_mint, 0);
+
+  emit!(Unstaked {
+    vault: vault.key(),
+    staker: staker_account.user,
+    signer: ctx.accounts.signer.key(),
+    mint: token_mint.key(),
+    unstake_fee: vault.unstake_fee,
+    reward_earned_pending: staker_account.reward_earned_pending,
+    mint_staked_count: staker_account.mint_staked_count,
+    total_reward_rate: staker_account.total_reward_rate,
+    total_staked: vault.total_staked,
+    timestamp: staker_account.last_update_time as i64,
+  });
 
   // Get the NFT from the Vault,
   let token_vault_name = &ctx.accounts.vault.name;
@@ -197,15 +299,102 @@ pub fn handle_unstake(ctx: Context<Unstake>) -> Result<()> {
   Ok(())
 }
 
+/*
+* Emergency Unstake:: Emergency Unstake Instruction - Thaw the user's NFT while the vault is paused.
+* The user gives up every pending reward and pays no fee.
+*/
+pub fn handle_emergency_unstake(ctx: Context<EmergencyUnstake>) -> Result<()> {
+  let vault = &mut ctx.accounts.vault;
+  let staker_account = &mut ctx.accounts.staker_account.load_mut()?;
+  let token_mint = &ctx.accounts.token_mint;
+  require_eq!(vault.paused, true, CustomError::NotPaused);
+
+  // Staker should own staker account
+  require_keys_eq!(
+    ctx.accounts.staker.key(),
+    staker_account.user.key(),
+    CustomError::KeyMismatch
+  );
+  require_keys_eq!(staker_account.vault, vault.key(), CustomError::IncorrectVault);
+
+  // Is correct mint
+  let mut is_owner = false;
+  for item in &staker_account.staked_items {
+    if item.mint == token_mint.key() {
+      is_owner = true;
+    }
+  }
+  require_eq!(is_owner, true, CustomError::Unauthorized);
+
+  // Forfeit everything earned so far, so update_accounts credits nothing.
+  let now: u64 = clock::Clock::get()
+    .unwrap()
+    .unix_timestamp
+    .try_into()
+    .unwrap();
+  staker_account.reward_earned_pending = 0;
+  staker_account.last_update_time = now;
+
+  update_accounts("unstake", vault, staker_account, token_mint, 0);
+
+  emit!(EmergencyUnstaked {
+    vault: vault.key(),
+    staker: staker_account.user,
+    mint: token_mint.key(),
+    mint_staked_count: staker_account.mint_staked_count,
+    total_reward_rate: staker_account.total_reward_rate,
+    total_staked: vault.total_staked,
+    timestamp: now as i64,
+  });
+
+  let token_vault_name = &ctx.accounts.vault.name;
+  let token_vault_bump = ctx.accounts.vault.bump;
+
+  let seeds = &[
+    b"vault".as_ref(),
+    token_vault_name.as_ref(),
+    &[token_vault_bump],
+  ];
+  invoke_signed(
+    &thaw_delegated_account(
+        ctx.accounts.token_metadata_program.key(),
+        ctx.accounts.vault.key(),
+        ctx.accounts.staker_ata.key(),
+        ctx.accounts.edition.key(),
+        ctx.accounts.token_mint.key(),
+    ),
+    &[
+        ctx.accounts.vault.to_account_info(),
+        ctx.accounts.staker_ata.to_account_info(),
+        ctx.accounts.edition.to_account_info(),
+        ctx.accounts.token_mint.to_account_info()
+    ],
+    &[seeds]
+  )?;
+
+  let cpi_context = CpiContext::new(
+      ctx.accounts.token_program.to_account_info(),
+      anchor_spl::token::Revoke {
+          source: ctx.accounts.staker_ata.to_account_info(),
+          authority: ctx.accounts.staker.to_account_info()
+      }
+  );
+
+  anchor_spl::token::revoke(cpi_context)?;
+
+  Ok(())
+}
 
 /*
 * Helper function to update the accounts.
+* `reward_rate` is only read when staking, unstaking removes the rate the item was staked with.
 */
 fn update_accounts(
   method: &str,
   vault: &mut Account<Vault>,
   staker_account: &mut RefMut<User>,
   token_mint: &Account<Mint>,
+  reward_rate: u64,
 ) {
   // Get the last time the account was updated.
   let last_update_time = staker_account.last_update_time;
@@ -245,12 +434,12 @@ fn update_accounts(
     // Add NFT that is being staked to user's mint staked account.
     staker_account.staked_items[index] = StakedNft {
       mint: token_mint.key(),
-      reward_rate: vault.payout_amount,
+      reward_rate,
       staked_time: now,
     };
     
     staker_account.total_reward_rate = staker_account.total_reward_rate.checked_add(
-      vault.payout_amount
+      reward_rate
     ).unwrap();
 
   }
@@ -263,13 +452,14 @@ fn update_accounts(
     staker_account.mint_staked_count = staker_account.mint_staked_count.checked_sub(1).unwrap();
     let last_index = staker_account.mint_staked_count as usize;
     let index = staker_account.staked_items.iter().position(|x| x.mint == token_mint.key()).unwrap();
+    let item_reward_rate = staker_account.staked_items[index].reward_rate;
    
     // Remove NFT that is being unstaked.
     staker_account.staked_items[index] = staker_account.staked_items[last_index];
     staker_account.staked_items[last_index] = StakedNft::default();
 
     staker_account.total_reward_rate = staker_account.total_reward_rate.checked_sub(
-      vault.payout_amount
+      item_reward_rate
     ).unwrap();
 
     msg!("Total Reward Rate: {:?}", staker_account.total_reward_rate);
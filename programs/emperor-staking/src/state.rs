@@ -1,7 +1,17 @@
 use anchor_lang::prelude::*;
 
 pub const MAX_NFT_PER_USER: usize = 150;
+pub const MAX_WHITELIST_ENTRIES: usize = 16;
 
+/// Which verified metadata fields an NFT must match to be staked in a vault.
+pub const WHITELIST_CREATOR: u8 = 0;
+pub const WHITELIST_COLLECTION: u8 = 1;
+pub const WHITELIST_CREATOR_AND_COLLECTION: u8 = 2;
+
+/*
+ * Fields up to `bump` keep the original layout, everything after it was appended later.
+ * Vaults created before that are brought over by `migrate_vault`.
+ */
 #[account]
 pub struct Vault {
     pub name: String,
@@ -16,10 +26,129 @@ pub struct Vault {
     pub stake_fee: u64,
     pub unstake_fee: u64,
     pub bump: u8,
+    /// Proposed by `authority`, takes over once it signs `accept_authority`.
+    pub pending_authority: Pubkey,
+    /// Sets the community wallet and the stake and unstake fees.
+    pub fee_manager: Pubkey,
+    /// Funds and drains the reward vault and sets payout rates.
+    pub rewards_manager: Pubkey,
+    pub pauser: Pubkey,
+    pub collection_address: Pubkey,
+    /// One of the `WHITELIST_*` modes.
+    pub whitelist_mode: u8,
+    /// Merkle root over keccak(mint, reward_rate) leaves, all zero while unset.
+    pub rarity_root: [u8; 32],
+    /// Blocks stake, claim and fund. Unstaking stays open.
+    pub paused: bool,
+    /// Minimum seconds between queuing a fee or payout change and executing it.
+    pub update_delay: u64,
+    pub pending_update: PendingUpdate,
 }
 
 impl Vault {
     pub const LEN: usize = std::mem::size_of::<Vault>();
+
+    /*
+     * Make the pending authority the vault authority and return the old one. Any role the
+     * old authority still held moves over as well, so the handover leaves it no access.
+     */
+    pub fn accept_authority(&mut self) -> Pubkey {
+        let old_authority = self.authority;
+        let new_authority = self.pending_authority;
+        self.authority = new_authority;
+        self.pending_authority = Pubkey::default();
+
+        for role in [
+            &mut self.fee_manager,
+            &mut self.rewards_manager,
+            &mut self.pauser,
+        ] {
+            if *role == old_authority {
+                *role = new_authority;
+            }
+        }
+        old_authority
+    }
+}
+
+/*
+ * Zero an account being closed. `close` only rewrites the discriminator, which would
+ * leave the rest of its data readable until the runtime reclaims it.
+ */
+pub fn clear_closed_account(account: &AccountInfo) -> Result<()> {
+    account.try_borrow_mut_data()?.fill(0);
+    Ok(())
+}
+
+/*
+ * Vault layout from before roles, pausing, queued updates and whitelists, only read by
+ * `migrate_vault`.
+ */
+#[derive(AnchorDeserialize)]
+pub struct LegacyVault {
+    pub name: String,
+    pub authority: Pubkey,
+    pub creator_address: Pubkey,
+    pub community_wallet: Pubkey,
+    pub total_earned: u64,
+    pub payout_interval: u64,
+    pub payout_amount: u64,
+    pub total_staked: u32,
+    pub total_amount: u64,
+    pub stake_fee: u64,
+    pub unstake_fee: u64,
+    pub bump: u8,
+}
+
+/*
+ * Extra creators and collections a vault accepts on top of its own rule, each earning
+ * its own reward rate, plus an optional merkle root over keccak(mint) of allowed mints.
+ */
+#[account]
+pub struct Whitelist {
+    pub vault: Pubkey,
+    pub entries: [WhitelistEntry; MAX_WHITELIST_ENTRIES],
+    pub entry_count: u8,
+    /// All zero while no mint list is set.
+    pub mint_root: [u8; 32],
+    pub mint_reward_rate: u64,
+    pub bump: u8,
+}
+
+impl Whitelist {
+    pub const LEN: usize = std::mem::size_of::<Whitelist>();
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct WhitelistEntry {
+    /// `WHITELIST_CREATOR` or `WHITELIST_COLLECTION`.
+    pub kind: u8,
+    pub address: Pubkey,
+    pub reward_rate: u64,
+}
+
+/*
+ * A staker's claim that `mint` earns `reward_rate` under the vault's `rarity_root`.
+ */
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, Default, PartialEq)]
+pub struct RarityProof {
+    pub reward_rate: u64,
+    pub proof: Vec<[u8; 32]>,
+}
+
+/*
+ * Fee and payout settings waiting out `update_delay`.
+ * `effective_at` is zero while nothing is queued.
+ */
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct PendingUpdate {
+    pub community_wallet: Pubkey,
+    pub stake_fee: u64,
+    pub unstake_fee: u64,
+    pub payout_interval: u64,
+    pub payout_amount: u64,
+    pub update_delay: u64,
+    pub effective_at: u64,
 }
 
 
@@ -41,6 +170,8 @@ pub struct User {
     pub last_update_time: u64,
     pub total_reward_rate: u64,
     pub mint_staked_count: u32,
+    /// Vault the account stakes in, set by its first stake. Rewards are only paid from it.
+    pub vault: Pubkey,
 }
 
 impl User {
@@ -61,6 +192,7 @@ impl Default for User {
             ],
             last_update_time: 0,
             total_reward_rate: 0,
+            vault: Pubkey::default(),
         }
     }
 }
@@ -1,9 +1,14 @@
 use std::cell::RefMut;
 
 use anchor_lang::prelude::*;
+use anchor_lang::Discriminator;
 use anchor_lang::solana_program::clock;
-use anchor_spl::token::{self};
+use anchor_lang::solana_program::program_option::COption;
+use anchor_spl::associated_token::{self, get_associated_token_address};
+use anchor_spl::token::{self, TokenAccount};
 
+use crate::errors::*;
+use crate::events::*;
 use crate::state::*;
 use crate::ins::*;
 /*
@@ -14,12 +19,19 @@ pub fn handle_create_stake_account(ctx: Context<CreateStakeAccount>) -> Result<(
 
   user_account.user = *ctx.accounts.user.key;
 
+  emit!(StakeAccountCreated {
+    user: user_account.user,
+    user_account: ctx.accounts.user_account.key(),
+    timestamp: Clock::get()?.unix_timestamp,
+  });
+
   Ok(())
 }
 
 pub fn handle_claim_rewards(ctx: Context<Claim>) -> Result<u64> {
   // Get the vault.
   let vault = &mut ctx.accounts.vault;
+  require_eq!(vault.paused, false, CustomError::Paused);
 
   // Get the current timestamp.
   let now: u64 = clock::Clock::get()
@@ -30,6 +42,14 @@ pub fn handle_claim_rewards(ctx: Context<Claim>) -> Result<u64> {
 
   let staker_account = &mut ctx.accounts.staker_account.load_mut()?;
 
+  // Rewards go to the account's owner and are paid by the vault they were earned in.
+  require_keys_eq!(
+    ctx.accounts.staker.key(),
+    staker_account.user.key(),
+    CustomError::KeyMismatch
+  );
+  require_keys_eq!(staker_account.vault, vault.key(), CustomError::IncorrectVault);
+
   // Update stakers earned Rewards.
   let staker_earned_amount_since_last_update = get_rewards_earned(
     now,
@@ -54,6 +74,16 @@ pub fn handle_claim_rewards(ctx: Context<Claim>) -> Result<u64> {
 
   vault.total_amount = vault.total_amount.checked_sub(staker_earned_amount).unwrap();
 
+  emit!(Claimed {
+    vault: vault.key(),
+    staker: staker_account.user,
+    mint: ctx.accounts.reward_token_mint.key(),
+    amount: staker_earned_amount,
+    reward_earned_claimed: staker_account.reward_earned_claimed,
+    total_amount: vault.total_amount,
+    timestamp: now as i64,
+  });
+
   let token_vault_name = &ctx.accounts.vault.name;
   let token_vault_bump = ctx.accounts.vault.bump;
 
@@ -78,6 +108,208 @@ pub fn handle_claim_rewards(ctx: Context<Claim>) -> Result<u64> {
   Ok(staker_earned_amount)
 }
 
+/*
+* User::Close Instruction - Close an empty stake account and return its rent to the user,
+* optionally paying out the last pending rewards first.
+*/
+pub fn handle_close_stake_account(ctx: Context<CloseStakeAccount>, payout: bool) -> Result<()> {
+  let vault = &mut ctx.accounts.vault;
+
+  // Get the current timestamp.
+  let now: u64 = clock::Clock::get()
+    .unwrap()
+    .unix_timestamp
+    .try_into()
+    .unwrap();
+
+  let (user, payout_amount) = {
+    let staker_account = &mut ctx.accounts.staker_account.load_mut()?;
+
+    // Staker should own staker account
+    require_keys_eq!(
+      ctx.accounts.staker.key(),
+      staker_account.user.key(),
+      CustomError::KeyMismatch
+    );
+    // An account that never staked is not tied to any vault and has nothing to pay out.
+    require!(
+      staker_account.vault == vault.key() || staker_account.vault == Pubkey::default(),
+      CustomError::IncorrectVault
+    );
+    require_eq!(staker_account.mint_staked_count, 0, CustomError::StakeAccountNotEmpty);
+
+    // Settle whatever accrued since the last unstake.
+    let staker_earned_amount_since_last_update = get_rewards_earned(
+      now,
+      staker_account.last_update_time,
+      staker_account,
+      vault,
+    );
+    staker_account.reward_earned_pending = staker_account
+      .reward_earned_pending
+      .checked_add(staker_earned_amount_since_last_update)
+      .unwrap();
+    staker_account.last_update_time = now;
+
+    let mut payout_amount = 0;
+    if payout && staker_account.reward_earned_pending > 0 {
+      require_eq!(vault.paused, false, CustomError::Paused);
+
+      payout_amount = staker_account.reward_earned_pending;
+      staker_account.reward_earned_pending = 0;
+      staker_account.reward_earned_claimed = staker_account
+        .reward_earned_claimed
+        .checked_add(payout_amount)
+        .unwrap();
+
+      vault.total_amount = vault.total_amount.checked_sub(payout_amount).unwrap();
+
+      emit!(Claimed {
+        vault: vault.key(),
+        staker: staker_account.user,
+        mint: ctx.accounts.reward_token_mint.key(),
+        amount: payout_amount,
+        reward_earned_claimed: staker_account.reward_earned_claimed,
+        total_amount: vault.total_amount,
+        timestamp: now as i64,
+      });
+    }
+
+    // Rewards left pending would be lost with the account.
+    require_eq!(staker_account.reward_earned_pending, 0, CustomError::StakeAccountNotEmpty);
+
+    (staker_account.user, payout_amount)
+  };
+
+  if payout_amount > 0 {
+    let reward_token_mint = ctx.accounts.reward_token_mint.key();
+    require_keys_eq!(
+      ctx.accounts.staker_ata.key(),
+      get_associated_token_address(&ctx.accounts.staker.key(), &reward_token_mint),
+      CustomError::KeyMismatch
+    );
+    require_keys_eq!(
+      ctx.accounts.reward_token_vault_ata.key(),
+      get_associated_token_address(&ctx.accounts.vault.key(), &reward_token_mint),
+      CustomError::KeyMismatch
+    );
+
+    // Only created when there is something to pay, so closing without a payout costs no rent.
+    if ctx.accounts.staker_ata.data_is_empty() {
+      associated_token::create(CpiContext::new(
+        ctx.accounts.associated_token_program.to_account_info(),
+        associated_token::Create {
+          payer: ctx.accounts.staker.to_account_info(),
+          associated_token: ctx.accounts.staker_ata.to_account_info(),
+          authority: ctx.accounts.staker.to_account_info(),
+          mint: ctx.accounts.reward_token_mint.to_account_info(),
+          system_program: ctx.accounts.system_program.to_account_info(),
+          token_program: ctx.accounts.token_program.to_account_info(),
+          rent: ctx.accounts.rent.to_account_info(),
+        },
+      ))?;
+    }
+
+    let token_vault_name = &ctx.accounts.vault.name;
+    let token_vault_bump = ctx.accounts.vault.bump;
+
+    let seeds = &[
+      b"vault".as_ref(),
+      token_vault_name.as_ref(),
+      &[token_vault_bump],
+    ];
+    let signer = &[&seeds[..]];
+
+    let token_ctx = CpiContext::new_with_signer(
+      ctx.accounts.token_program.to_account_info(),
+      token::Transfer {
+        authority: ctx.accounts.vault.to_account_info(),
+        from: ctx.accounts.reward_token_vault_ata.to_account_info(),
+        to: ctx.accounts.staker_ata.to_account_info(),
+      },
+      signer,
+    );
+    token::transfer(token_ctx, payout_amount)?;
+  }
+
+  emit!(StakeAccountClosed {
+    user,
+    user_account: ctx.accounts.staker_account.key(),
+    timestamp: now as i64,
+  });
+
+  clear_closed_account(&ctx.accounts.staker_account.to_account_info())?;
+
+  Ok(())
+}
+
+/*
+* User::Migrate Instruction - Grow a stake account created before accounts were tied to a vault,
+* signed by its owner. An account with NFTs staked is tied to the vault they are delegated to
+* and frozen for, one without is tied by its next stake.
+*/
+pub fn handle_migrate_stake_account(ctx: Context<MigrateStakeAccount>) -> Result<()> {
+  let staker_account_info = ctx.accounts.staker_account.to_account_info();
+  let new_len = User::LEN + 8;
+  {
+    let data = staker_account_info.try_borrow_data()?;
+    require!(data.len() < new_len, CustomError::AlreadyMigrated);
+    require!(
+      data[..8] == User::discriminator(),
+      ErrorCode::AccountDiscriminatorMismatch
+    );
+  }
+
+  // The staker pays the rent for the larger account.
+  let rent_due = Rent::get()?
+    .minimum_balance(new_len)
+    .saturating_sub(staker_account_info.lamports());
+  if rent_due > 0 {
+    anchor_lang::system_program::transfer(
+      CpiContext::new(
+        ctx.accounts.system_program.to_account_info(),
+        anchor_lang::system_program::Transfer {
+          from: ctx.accounts.staker.to_account_info(),
+          to: staker_account_info.clone(),
+        },
+      ),
+      rent_due,
+    )?;
+  }
+  staker_account_info.realloc(new_len, true)?;
+
+  let staker_account = AccountLoader::<User>::try_from(&staker_account_info)?;
+  let mut staker_account = staker_account.load_mut()?;
+  require_keys_eq!(
+    ctx.accounts.staker.key(),
+    staker_account.user,
+    CustomError::KeyMismatch
+  );
+
+  // Only the vault could have frozen a staked NFT with itself as delegate, which proves where
+  // the account stakes.
+  if staker_account.mint_staked_count > 0 {
+    let token_account_info = ctx.accounts.staked_token_account.to_account_info();
+    let token_account = Account::<TokenAccount>::try_from(&token_account_info)?;
+    require!(
+      token_account.mint == staker_account.staked_items[0].mint
+        && token_account.owner == staker_account.user
+        && token_account.delegate == COption::Some(ctx.accounts.vault.key())
+        && token_account.is_frozen(),
+      CustomError::IncorrectVault
+    );
+    staker_account.vault = ctx.accounts.vault.key();
+  }
+
+  emit!(StakeAccountMigrated {
+    user: staker_account.user,
+    user_account: staker_account_info.key(),
+    vault: staker_account.vault,
+    timestamp: Clock::get()?.unix_timestamp,
+  });
+
+  Ok(())
+}
 
 /*
 * Helper function to get the rewards earned by the staker.
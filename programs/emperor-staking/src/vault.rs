@@ -1,8 +1,10 @@
 use crate::errors::*;
+use crate::events::*;
 use crate::ins::*;
 use crate::state::*;
 
 use anchor_lang::prelude::*;
+use anchor_lang::Discriminator;
 use anchor_spl::token::{self};
 
 
@@ -22,7 +24,12 @@ pub fn handle_vault_initialization(
   let vault = &mut ctx.accounts.vault;
   vault.name = vault_name;
   vault.authority = ctx.accounts.authority.key();
+  vault.fee_manager = vault.authority;
+  vault.rewards_manager = vault.authority;
+  vault.pauser = vault.authority;
   vault.creator_address = creator_address;
+  // Vaults start out matching on the verified creator, `update_vault` switches the rule.
+  vault.whitelist_mode = WHITELIST_CREATOR;
   vault.community_wallet = community_wallet;
   vault.payout_interval = payout_interval;
   vault.payout_amount = payout_amount;
@@ -35,21 +42,107 @@ pub fn handle_vault_initialization(
   msg!("Staked Item Size: {:?}", StakedNft::LEN);
 
   vault.bump = *ctx.bumps.get("vault").unwrap();
+
+  let whitelist = &mut ctx.accounts.whitelist;
+  whitelist.vault = vault.key();
+  whitelist.bump = *ctx.bumps.get("whitelist").unwrap();
+
+  emit!(VaultInitialized {
+    vault: vault.key(),
+    authority: vault.authority,
+    creator_address,
+    community_wallet,
+    payout_interval,
+    payout_amount,
+    stake_fee,
+    unstake_fee,
+    timestamp: Clock::get()?.unix_timestamp,
+  });
   Ok(())
 }
 
 /*
-* Vault:: Update Instruction - Update the vault account.
+* Vault:: Update Instruction - Update the creator, collection and whitelist rule the vault accepts.
 */
 pub fn handle_vault_update(
   ctx: Context<UpdateVault>,
   creator_address: Pubkey,
-  payout_interval: u64,
-  payout_amount: u64,
+  collection_address: Pubkey,
+  whitelist_mode: u8,
+) -> Result<()> {
+  require_keys_eq!(
+    ctx.accounts.vault.authority,
+    ctx.accounts.authority.key(),
+    CustomError::Unauthorized
+  );
+  require!(
+    whitelist_mode <= WHITELIST_CREATOR_AND_COLLECTION,
+    CustomError::InvalidWhitelistMode
+  );
+  let vault = &mut ctx.accounts.vault;
+
+  vault.creator_address = creator_address;
+  vault.collection_address = collection_address;
+  vault.whitelist_mode = whitelist_mode;
+
+  emit!(VaultUpdated {
+    vault: vault.key(),
+    creator_address,
+    collection_address,
+    whitelist_mode,
+    timestamp: Clock::get()?.unix_timestamp,
+  });
+  Ok(())
+}
+
+/*
+* Vault:: Update Fees Instruction - Queue new fees and fee wallet behind the update delay.
+*/
+pub fn handle_fees_update(
+  ctx: Context<UpdateVault>,
   community_wallet: Pubkey,
   stake_fee: u64,
   unstake_fee: u64,
 ) -> Result<()> {
+  require_keys_eq!(
+    ctx.accounts.vault.fee_manager,
+    ctx.accounts.authority.key(),
+    CustomError::Unauthorized
+  );
+  let vault = &mut ctx.accounts.vault;
+
+  let mut pending_update = get_pending_update(vault);
+  pending_update.community_wallet = community_wallet;
+  pending_update.stake_fee = stake_fee;
+  pending_update.unstake_fee = unstake_fee;
+  queue_update(vault, pending_update)
+}
+
+/*
+* Vault:: Update Payout Instruction - Queue a new reward rate behind the update delay.
+*/
+pub fn handle_payout_update(
+  ctx: Context<UpdateVault>,
+  payout_interval: u64,
+  payout_amount: u64,
+) -> Result<()> {
+  require_keys_eq!(
+    ctx.accounts.vault.rewards_manager,
+    ctx.accounts.authority.key(),
+    CustomError::Unauthorized
+  );
+  let vault = &mut ctx.accounts.vault;
+
+  let mut pending_update = get_pending_update(vault);
+  pending_update.payout_interval = payout_interval;
+  pending_update.payout_amount = payout_amount;
+  queue_update(vault, pending_update)
+}
+
+/*
+* Vault:: Set Update Delay Instruction - Queue a new update delay behind the current one.
+*/
+pub fn handle_set_update_delay(ctx: Context<UpdateVault>, update_delay: u64) -> Result<()> {
   require_keys_eq!(
     ctx.accounts.vault.authority,
     ctx.accounts.authority.key(),
@@ -57,13 +150,138 @@ pub fn handle_vault_update(
   );
   let vault = &mut ctx.accounts.vault;
 
-  vault.creator_address = creator_address;
-  vault.community_wallet = community_wallet;
-  vault.payout_interval = payout_interval;
-  vault.payout_amount = payout_amount;
-  vault.stake_fee = stake_fee;
-  vault.unstake_fee = unstake_fee;
+  let mut pending_update = get_pending_update(vault);
+  pending_update.update_delay = update_delay;
+  queue_update(vault, pending_update)
+}
+
+/*
+* Vault:: Execute Pending Update Instruction - Apply a queued update once its delay has passed.
+* Anyone can call it.
+*/
+pub fn handle_execute_pending_update(ctx: Context<ExecutePendingUpdate>) -> Result<()> {
+  let vault = &mut ctx.accounts.vault;
+  let pending_update = vault.pending_update;
+  let now = Clock::get()?.unix_timestamp;
+  require_neq!(pending_update.effective_at, 0, CustomError::NoPendingUpdate);
+  require_gte!(now as u64, pending_update.effective_at, CustomError::UpdateNotReady);
 
+  vault.community_wallet = pending_update.community_wallet;
+  vault.stake_fee = pending_update.stake_fee;
+  vault.unstake_fee = pending_update.unstake_fee;
+  vault.payout_interval = pending_update.payout_interval;
+  vault.payout_amount = pending_update.payout_amount;
+  vault.update_delay = pending_update.update_delay;
+  vault.pending_update = PendingUpdate::default();
+
+  emit!(UpdateExecuted {
+    vault: vault.key(),
+    community_wallet: vault.community_wallet,
+    stake_fee: vault.stake_fee,
+    unstake_fee: vault.unstake_fee,
+    payout_interval: vault.payout_interval,
+    payout_amount: vault.payout_amount,
+    update_delay: vault.update_delay,
+    timestamp: now,
+  });
+  Ok(())
+}
+
+/*
+* Vault:: Cancel Pending Update Instruction - Drop a queued update before it is executed.
+*/
+pub fn handle_cancel_pending_update(ctx: Context<UpdateVault>) -> Result<()> {
+  require_keys_eq!(
+    ctx.accounts.vault.authority,
+    ctx.accounts.authority.key(),
+    CustomError::Unauthorized
+  );
+  require_neq!(
+    ctx.accounts.vault.pending_update.effective_at,
+    0,
+    CustomError::NoPendingUpdate
+  );
+
+  let vault = &mut ctx.accounts.vault;
+  vault.pending_update = PendingUpdate::default();
+
+  emit!(UpdateCancelled {
+    vault: vault.key(),
+    timestamp: Clock::get()?.unix_timestamp,
+  });
+  Ok(())
+}
+
+/*
+* Helper function to get the queued update, or the current settings if nothing is queued.
+* Queuing a change keeps any other change already waiting.
+*/
+#[inline(always)]
+fn get_pending_update(vault: &Vault) -> PendingUpdate {
+  if vault.pending_update.effective_at != 0 {
+    return vault.pending_update;
+  }
+
+  PendingUpdate {
+    community_wallet: vault.community_wallet,
+    stake_fee: vault.stake_fee,
+    unstake_fee: vault.unstake_fee,
+    payout_interval: vault.payout_interval,
+    payout_amount: vault.payout_amount,
+    update_delay: vault.update_delay,
+    effective_at: 0,
+  }
+}
+
+/*
+* Helper function to queue an update behind the current delay, restarting the wait.
+*/
+fn queue_update(vault: &mut Account<Vault>, mut pending_update: PendingUpdate) -> Result<()> {
+  let now = Clock::get()?.unix_timestamp;
+  pending_update.effective_at = (now as u64).checked_add(vault.update_delay).unwrap();
+  vault.pending_update = pending_update;
+
+  emit!(UpdateQueued {
+    vault: vault.key(),
+    community_wallet: pending_update.community_wallet,
+    stake_fee: pending_update.stake_fee,
+    unstake_fee: pending_update.unstake_fee,
+    payout_interval: pending_update.payout_interval,
+    payout_amount: pending_update.payout_amount,
+    update_delay: pending_update.update_delay,
+    effective_at: pending_update.effective_at,
+    timestamp: now,
+  });
+  Ok(())
+}
+
+/*
+* Vault:: Set Roles Instruction - Assign the fee manager, rewards manager and pauser.
+*/
+pub fn handle_set_roles(
+  ctx: Context<UpdateVault>,
+  fee_manager: Pubkey,
+  rewards_manager: Pubkey,
+  pauser: Pubkey,
+) -> Result<()> {
+  require_keys_eq!(
+    ctx.accounts.vault.authority,
+    ctx.accounts.authority.key(),
+    CustomError::Unauthorized
+  );
+  let vault = &mut ctx.accounts.vault;
+
+  vault.fee_manager = fee_manager;
+  vault.rewards_manager = rewards_manager;
+  vault.pauser = pauser;
+
+  emit!(RolesUpdated {
+    vault: vault.key(),
+    fee_manager,
+    rewards_manager,
+    pauser,
+    timestamp: Clock::get()?.unix_timestamp,
+  });
   Ok(())
 }
 
@@ -71,6 +289,13 @@ pub fn handle_vault_update(
 * Vault:: Fund Instruction - Fund the vault account with tokens.
 */
 pub fn handle_fund(ctx: Context<FundTokenVault>, amount: u64) -> Result<()> {
+  require_keys_eq!(
+    ctx.accounts.vault.rewards_manager,
+    ctx.accounts.funder.key(),
+    CustomError::Unauthorized
+  );
+  require_eq!(ctx.accounts.vault.paused, false, CustomError::Paused);
+
   let token_ctx = CpiContext::new(
     ctx.accounts.token_program.to_account_info(),
     token::Transfer {
@@ -82,6 +307,15 @@ pub fn handle_fund(ctx: Context<FundTokenVault>, amount: u64) -> Result<()> {
   token::transfer(token_ctx, amount)?;
   let vault = &mut ctx.accounts.vault;
   vault.total_amount = vault.total_amount.checked_add(amount).unwrap();
+
+  emit!(Funded {
+    vault: vault.key(),
+    funder: ctx.accounts.funder.key(),
+    mint: ctx.accounts.reward_token_mint.key(),
+    amount,
+    total_amount: vault.total_amount,
+    timestamp: Clock::get()?.unix_timestamp,
+  });
   Ok(())
 }
 
@@ -90,7 +324,7 @@ pub fn handle_fund(ctx: Context<FundTokenVault>, amount: u64) -> Result<()> {
 */
 pub fn handle_drain(ctx: Context<DrainTokenVault>, amount: u64) -> Result<()> {
   require_keys_eq!(
-    ctx.accounts.vault.authority.key(),
+    ctx.accounts.vault.rewards_manager,
     ctx.accounts.funder.key(),
     CustomError::Unauthorized
   );
@@ -117,20 +351,359 @@ pub fn handle_drain(ctx: Context<DrainTokenVault>, amount: u64) -> Result<()> {
   token::transfer(token_ctx, amount)?;
   let vault = &mut ctx.accounts.vault;
   vault.total_amount = vault.total_amount.checked_sub(amount).unwrap();
+
+  emit!(Drained {
+    vault: vault.key(),
+    authority: ctx.accounts.funder.key(),
+    mint: ctx.accounts.reward_token_mint.key(),
+    amount,
+    total_amount: vault.total_amount,
+    timestamp: Clock::get()?.unix_timestamp,
+  });
+  Ok(())
+}
+
+/*
+* Vault:: Propose Authority Instruction - Propose a new authority over the vault.
+* Nothing changes until the new authority accepts.
+*/
+pub fn handle_propose_authority(ctx: Context<SetVaultAuthority>) -> Result<()> {
+  require_keys_eq!(
+    ctx.accounts.vault.authority,
+    ctx.accounts.authority.key(),
+    CustomError::Unauthorized
+  );
+
+  let vault = &mut ctx.accounts.vault;
+  vault.pending_authority = ctx.accounts.new_authority.key();
+
+  emit!(AuthorityProposed {
+    vault: vault.key(),
+    authority: vault.authority,
+    pending_authority: vault.pending_authority,
+    timestamp: Clock::get()?.unix_timestamp,
+  });
+  Ok(())
+}
+
+/*
+* Vault:: Cancel Authority Instruction - Withdraw a pending authority proposal.
+*/
+pub fn handle_cancel_authority(ctx: Context<UpdateVault>) -> Result<()> {
+  require_keys_eq!(
+    ctx.accounts.vault.authority,
+    ctx.accounts.authority.key(),
+    CustomError::Unauthorized
+  );
+  require_keys_neq!(
+    ctx.accounts.vault.pending_authority,
+    Pubkey::default(),
+    CustomError::NoPendingAuthority
+  );
+
+  let vault = &mut ctx.accounts.vault;
+  let pending_authority = vault.pending_authority;
+  vault.pending_authority = Pubkey::default();
+
+  emit!(AuthorityProposalCancelled {
+    vault: vault.key(),
+    authority: vault.authority,
+    pending_authority,
+    timestamp: Clock::get()?.unix_timestamp,
+  });
+  Ok(())
+}
+
+/*
+* Vault:: Accept Authority Instruction - The proposed authority takes over the vault.
+*/
+pub fn handle_accept_authority(ctx: Context<AcceptAuthority>) -> Result<()> {
+  require_keys_eq!(
+    ctx.accounts.vault.pending_authority,
+    ctx.accounts.pending_authority.key(),
+    CustomError::Unauthorized
+  );
+
+  let vault = &mut ctx.accounts.vault;
+  let old_authority = vault.accept_authority();
+  let new_authority = vault.authority;
+
+  let timestamp = Clock::get()?.unix_timestamp;
+  emit!(AuthorityChanged {
+    vault: vault.key(),
+    old_authority,
+    new_authority,
+    timestamp,
+  });
+  emit!(RolesUpdated {
+    vault: vault.key(),
+    fee_manager: vault.fee_manager,
+    rewards_manager: vault.rewards_manager,
+    pauser: vault.pauser,
+    timestamp,
+  });
+  Ok(())
+}
+
+/*
+* Vault:: Set Paused Instruction - Pause or resume the vault.
+*/
+pub fn handle_set_paused(ctx: Context<UpdateVault>, paused: bool) -> Result<()> {
+  require_keys_eq!(
+    ctx.accounts.vault.pauser,
+    ctx.accounts.authority.key(),
+    CustomError::Unauthorized
+  );
+
+  let vault = &mut ctx.accounts.vault;
+  vault.paused = paused;
+
+  emit!(VaultPaused {
+    vault: vault.key(),
+    paused,
+    timestamp: Clock::get()?.unix_timestamp,
+  });
+  Ok(())
+}
+
+/*
+* Vault:: Close Instruction - Return the vault's rent to a chosen recipient once nothing is
+* staked and every reward token has been drained or claimed.
+*/
+pub fn handle_close_vault(ctx: Context<CloseVault>) -> Result<()> {
+  require_keys_eq!(
+    ctx.accounts.vault.authority,
+    ctx.accounts.authority.key(),
+    CustomError::Unauthorized
+  );
+  require!(
+    ctx.accounts.vault.total_staked == 0 && ctx.accounts.vault.total_amount == 0,
+    CustomError::VaultNotEmpty
+  );
+
+  emit!(VaultClosed {
+    vault: ctx.accounts.vault.key(),
+    authority: ctx.accounts.authority.key(),
+    recipient: ctx.accounts.recipient.key(),
+    timestamp: Clock::get()?.unix_timestamp,
+  });
+
+  clear_closed_account(&ctx.accounts.vault.to_account_info())?;
+  clear_closed_account(&ctx.accounts.whitelist.to_account_info())?;
+  Ok(())
+}
+
+/*
+* Vault:: Add Whitelist Entry Instruction - Accept NFTs with a verified creator or collection
+* at their own reward rate.
+*/
+pub fn handle_add_whitelist_entry(
+  ctx: Context<UpdateWhitelist>,
+  kind: u8,
+  address: Pubkey,
+  reward_rate: u64,
+) -> Result<()> {
+  require_keys_eq!(
+    ctx.accounts.vault.authority,
+    ctx.accounts.authority.key(),
+    CustomError::Unauthorized
+  );
+  require!(
+    kind == WHITELIST_CREATOR || kind == WHITELIST_COLLECTION,
+    CustomError::InvalidWhitelistMode
+  );
+
+  let whitelist = &mut ctx.accounts.whitelist;
+  let entry_count = whitelist.entry_count as usize;
+  require!(
+    !whitelist.entries[..entry_count]
+      .iter()
+      .any(|entry| entry.kind == kind && entry.address == address),
+    CustomError::DuplicateWhitelistEntry
+  );
+  require!(entry_count < MAX_WHITELIST_ENTRIES, CustomError::WhitelistFull);
+
+  whitelist.entries[entry_count] = WhitelistEntry {
+    kind,
+    address,
+    reward_rate,
+  };
+  whitelist.entry_count += 1;
+
+  emit!(WhitelistEntryAdded {
+    vault: whitelist.vault,
+    kind,
+    address,
+    reward_rate,
+    entry_count: whitelist.entry_count,
+    timestamp: Clock::get()?.unix_timestamp,
+  });
+  Ok(())
+}
+
+/*
+* Vault:: Remove Whitelist Entry Instruction - Stop accepting new stakes for an entry.
+* NFTs already staked keep earning at the rate they were staked with.
+*/
+pub fn handle_remove_whitelist_entry(
+  ctx: Context<UpdateWhitelist>,
+  kind: u8,
+  address: Pubkey,
+) -> Result<()> {
+  require_keys_eq!(
+    ctx.accounts.vault.authority,
+    ctx.accounts.authority.key(),
+    CustomError::Unauthorized
+  );
+
+  let whitelist = &mut ctx.accounts.whitelist;
+  let last_index = (whitelist.entry_count as usize)
+    .checked_sub(1)
+    .ok_or(CustomError::WhitelistEntryNotFound)?;
+  let index = whitelist.entries[..=last_index]
+    .iter()
+    .position(|entry| entry.kind == kind && entry.address == address)
+    .ok_or(CustomError::WhitelistEntryNotFound)?;
+
+  whitelist.entries[index] = whitelist.entries[last_index];
+  whitelist.entries[last_index] = WhitelistEntry::default();
+  whitelist.entry_count -= 1;
+
+  emit!(WhitelistEntryRemoved {
+    vault: whitelist.vault,
+    kind,
+    address,
+    entry_count: whitelist.entry_count,
+    timestamp: Clock::get()?.unix_timestamp,
+  });
   Ok(())
 }
 
 /*
-* Vault:: Set Authority Instruction - Set a new authority over the vault.
+* Vault:: Set Whitelist Mint Root Instruction - Accept individually listed mints at one reward rate.
+* An all zero root turns the mint list off.
 */
-pub fn handle_set_vault_authority(ctx: Context<SetVaultAuthority>) -> Result<()> {
+pub fn handle_set_whitelist_mint_root(
+  ctx: Context<UpdateWhitelist>,
+  mint_root: [u8; 32],
+  mint_reward_rate: u64,
+) -> Result<()> {
   require_keys_eq!(
     ctx.accounts.vault.authority,
     ctx.accounts.authority.key(),
     CustomError::Unauthorized
   );
 
+  let whitelist = &mut ctx.accounts.whitelist;
+  whitelist.mint_root = mint_root;
+  whitelist.mint_reward_rate = mint_reward_rate;
+
+  emit!(WhitelistMintRootSet {
+    vault: whitelist.vault,
+    mint_root,
+    mint_reward_rate,
+    timestamp: Clock::get()?.unix_timestamp,
+  });
+  Ok(())
+}
+
+/*
+* Vault:: Set Rarity Root Instruction - Publish the merkle root of per-mint reward rates.
+* NFTs already staked keep the rate they were staked with, an all zero root turns it off.
+*/
+pub fn handle_set_rarity_root(ctx: Context<UpdateVault>, rarity_root: [u8; 32]) -> Result<()> {
+  require_keys_eq!(
+    ctx.accounts.vault.authority,
+    ctx.accounts.authority.key(),
+    CustomError::Unauthorized
+  );
   let vault = &mut ctx.accounts.vault;
-  vault.authority = ctx.accounts.new_authority.key();
+  vault.rarity_root = rarity_root;
+
+  emit!(RarityRootSet {
+    vault: vault.key(),
+    rarity_root,
+    timestamp: Clock::get()?.unix_timestamp,
+  });
+  Ok(())
+}
+
+/*
+* Vault:: Migrate Instruction - Rewrite a vault created before roles, pausing, queued updates
+* and whitelists into the current layout, and create its whitelist.
+*/
+pub fn handle_migrate_vault(ctx: Context<MigrateVault>) -> Result<()> {
+  let vault_info = ctx.accounts.vault.to_account_info();
+  let new_len = Vault::LEN + 8;
+
+  let legacy_vault = {
+    let data = vault_info.try_borrow_data()?;
+    require!(data.len() < new_len, CustomError::AlreadyMigrated);
+    require!(
+      data[..8] == Vault::discriminator(),
+      ErrorCode::AccountDiscriminatorMismatch
+    );
+    LegacyVault::deserialize(&mut &data[8..])?
+  };
+  require_keys_eq!(
+    legacy_vault.authority,
+    ctx.accounts.authority.key(),
+    CustomError::Unauthorized
+  );
+
+  // The authority pays the rent for the larger account.
+  let rent_due = Rent::get()?
+    .minimum_balance(new_len)
+    .saturating_sub(vault_info.lamports());
+  if rent_due > 0 {
+    anchor_lang::system_program::transfer(
+      CpiContext::new(
+        ctx.accounts.system_program.to_account_info(),
+        anchor_lang::system_program::Transfer {
+          from: ctx.accounts.authority.to_account_info(),
+          to: vault_info.clone(),
+        },
+      ),
+      rent_due,
+    )?;
+  }
+  vault_info.realloc(new_len, true)?;
+
+  // Every role starts with the authority, as on a fresh vault.
+  let vault = Vault {
+    name: legacy_vault.name,
+    authority: legacy_vault.authority,
+    creator_address: legacy_vault.creator_address,
+    community_wallet: legacy_vault.community_wallet,
+    total_earned: legacy_vault.total_earned,
+    payout_interval: legacy_vault.payout_interval,
+    payout_amount: legacy_vault.payout_amount,
+    total_staked: legacy_vault.total_staked,
+    total_amount: legacy_vault.total_amount,
+    stake_fee: legacy_vault.stake_fee,
+    unstake_fee: legacy_vault.unstake_fee,
+    bump: legacy_vault.bump,
+    pending_authority: Pubkey::default(),
+    fee_manager: legacy_vault.authority,
+    rewards_manager: legacy_vault.authority,
+    pauser: legacy_vault.authority,
+    collection_address: Pubkey::default(),
+    whitelist_mode: WHITELIST_CREATOR,
+    rarity_root: [0; 32],
+    paused: false,
+    update_delay: 0,
+    pending_update: PendingUpdate::default(),
+  };
+  vault.try_serialize(&mut &mut vault_info.try_borrow_mut_data()?[..])?;
+
+  let whitelist = &mut ctx.accounts.whitelist;
+  whitelist.vault = vault_info.key();
+  whitelist.bump = *ctx.bumps.get("whitelist").unwrap();
+
+  emit!(VaultMigrated {
+    vault: vault_info.key(),
+    authority: vault.authority,
+    timestamp: Clock::get()?.unix_timestamp,
+  });
   Ok(())
 }
@@ -0,0 +1,68 @@
+use anchor_lang::prelude::*;
+
+// Custom Program Errors
+#[error_code]
+pub enum CustomError {
+  #[msg("Mint is not a reward of the vault")]
+  RewardNotFound,
+  #[msg("Vault already has the maximum number of rewards")]
+  MaxRewards,
+  #[msg("Mint is already a reward of the vault")]
+  DuplicateReward,
+  #[msg("Expected a vault and staker token account for every reward")]
+  InvalidRemainingAccounts,
+  #[msg("Token account does not match the reward")]
+  InvalidTokenAccount,
+  #[msg("Lock tier does not exist")]
+  InvalidLockTier,
+  #[msg("Stake is still locked")]
+  Locked,
+  #[msg("Unstaked tokens are still unbonding")]
+  Unbonding,
+  #[msg("Reward period must end after it starts")]
+  InvalidPeriod,
+  #[msg("Not enough unallocated rewards in the pool")]
+  InsufficientRewards,
+  #[msg("Unauthorized access")]
+  Unauthorized,
+  #[msg("Vault is paused")]
+  Paused,
+  #[msg("Vault is not paused")]
+  NotPaused,
+  #[msg("No authority handover is pending")]
+  NoPendingAuthority,
+  #[msg("No settings change is queued")]
+  NoPendingUpdate,
+  #[msg("Queued settings change is not effective yet")]
+  UpdateNotReady,
+  #[msg("Fee can not exceed 10000 basis points")]
+  InvalidFee,
+  #[msg("Penalty must be at most 10000 basis points with a known mode")]
+  InvalidPenalty,
+  #[msg("Stakers can not refer themselves")]
+  InvalidReferrer,
+  #[msg("Referral share can not exceed 10000 basis points")]
+  InvalidReferralShare,
+  #[msg("Account is not a position referred by the signer in this vault")]
+  NotReferee,
+  #[msg("Arithmetic overflow")]
+  MathOverflow,
+  #[msg("Amount exceeds the staked balance")]
+  InsufficientStake,
+  #[msg("Position still has stake, unbonding tokens or unclaimed rewards")]
+  PositionNotEmpty,
+  #[msg("Vault still has stake, owed rewards or reward pool tokens")]
+  VaultNotEmpty,
+  #[msg("Vault has not been closed")]
+  VaultNotClosed,
+  #[msg("Stake mint can not change while tokens are staked or unbonding")]
+  StakeMintInUse,
+  #[msg("Account is not a legacy vault in the expected migration state")]
+  InvalidLegacyVault,
+  #[msg("Legacy vault has no entry for the staker at that index")]
+  LegacyStakerNotFound,
+  #[msg("Clock returned a negative timestamp")]
+  InvalidTimestamp,
+  #[msg("PDA bump was not found")]
+  BumpNotFound,
+}
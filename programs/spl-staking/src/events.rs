@@ -0,0 +1,338 @@
+use anchor_lang::prelude::*;
+
+/*
+ * Emitted after every state change, carrying the resulting totals so the
+ * vault's history can be rebuilt from logs alone.
+ */
+#[event]
+pub struct VaultInitialized {
+    pub vault: Pubkey,
+    pub authority: Pubkey,
+    pub stake_token_mint: Pubkey,
+    pub reward_mint: Pubkey,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct VaultUpdateQueued {
+    pub vault: Pubkey,
+    pub stake_token_mint: Pubkey,
+    pub unbonding_period: u64,
+    pub update_delay: u64,
+    pub penalty_period: u64,
+    pub penalty_bps: u16,
+    pub penalty_mode: u8,
+    pub effective_at: u64,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct VaultUpdated {
+    pub vault: Pubkey,
+    pub stake_token_mint: Pubkey,
+    pub unbonding_period: u64,
+    pub update_delay: u64,
+    pub penalty_period: u64,
+    pub penalty_bps: u16,
+    pub penalty_mode: u8,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct PendingUpdateCancelled {
+    pub vault: Pubkey,
+    pub fee_vault: Pubkey,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct AuthorityChanged {
+    pub vault: Pubkey,
+    pub old_authority: Pubkey,
+    pub new_authority: Pubkey,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct RolesUpdated {
+    pub vault: Pubkey,
+    pub fee_manager: Pubkey,
+    pub rewards_manager: Pubkey,
+    pub pauser: Pubkey,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct ReferralShareSet {
+    pub vault: Pubkey,
+    pub referral_bps: u16,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct AuthorityProposed {
+    pub vault: Pubkey,
+    pub authority: Pubkey,
+    pub pending_authority: Pubkey,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct AuthorityProposalCancelled {
+    pub vault: Pubkey,
+    pub authority: Pubkey,
+    pub pending_authority: Pubkey,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct VaultPaused {
+    pub vault: Pubkey,
+    pub paused: bool,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct RewardAdded {
+    pub vault: Pubkey,
+    pub mint: Pubkey,
+    pub reward_count: u8,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct Funded {
+    pub vault: Pubkey,
+    pub funder: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub period_amount: u64,
+    pub period_start_time: u64,
+    pub period_end_time: u64,
+    pub reward_pool_amount: u64,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct Withdrawn {
+    pub vault: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub reward_pool_amount: u64,
+    pub allocated_amount: u64,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct Staked {
+    pub vault: Pubkey,
+    pub staker: Pubkey,
+    pub amount: u64,
+    pub lock_tier: u8,
+    pub stake_fee: u64,
+    pub fee_amount: u64,
+    pub staked_amount: u64,
+    pub weighted_amount: u64,
+    pub lock_end_time: u64,
+    pub total_staked_amount: u64,
+    pub total_weighted_amount: u64,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct ReferrerSet {
+    pub vault: Pubkey,
+    pub staker: Pubkey,
+    pub referrer: Pubkey,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct StakedWithClaim {
+    pub vault: Pubkey,
+    pub staker: Pubkey,
+    pub emperor_vault: Pubkey,
+    pub amount: u64,
+    pub stake_fee: u64,
+    pub fee_amount: u64,
+    pub staked_amount: u64,
+    pub weighted_amount: u64,
+    pub total_staked_amount: u64,
+    pub total_weighted_amount: u64,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct Compounded {
+    pub vault: Pubkey,
+    pub staker: Pubkey,
+    pub signer: Pubkey,
+    pub amount: u64,
+    pub fee_amount: u64,
+    pub staked_amount: u64,
+    pub weighted_amount: u64,
+    pub total_staked_amount: u64,
+    pub total_weighted_amount: u64,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct Unstaked {
+    pub vault: Pubkey,
+    pub staker: Pubkey,
+    pub amount: u64,
+    pub unstake_fee: u64,
+    pub fee_amount: u64,
+    pub penalty_amount: u64,
+    pub staked_amount: u64,
+    pub unbonding_amount: u64,
+    pub unbonding_end_time: u64,
+    pub total_staked_amount: u64,
+    pub total_weighted_amount: u64,
+    pub total_unbonding_amount: u64,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct UnbondedWithdrawn {
+    pub vault: Pubkey,
+    pub staker: Pubkey,
+    pub amount: u64,
+    pub total_unbonding_amount: u64,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct EmergencyWithdrawn {
+    pub vault: Pubkey,
+    pub staker: Pubkey,
+    pub amount: u64,
+    pub total_staked_amount: u64,
+    pub total_weighted_amount: u64,
+    pub total_unbonding_amount: u64,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct LockExpired {
+    pub vault: Pubkey,
+    pub staker: Pubkey,
+    pub weighted_amount: u64,
+    pub total_weighted_amount: u64,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct Claimed {
+    pub vault: Pubkey,
+    pub staker: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub fee_amount: u64,
+    pub reward_pool_amount: u64,
+    pub allocated_amount: u64,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct ReferralClaimed {
+    pub vault: Pubkey,
+    pub referrer: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub referee_count: u32,
+    pub claimed_amount: u64,
+    pub reward_pool_amount: u64,
+    pub allocated_amount: u64,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct AutoCompoundSet {
+    pub vault: Pubkey,
+    pub staker: Pubkey,
+    pub auto_compound: bool,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct VaultMigrated {
+    pub vault: Pubkey,
+    pub legacy_vault: Pubkey,
+    pub authority: Pubkey,
+    pub stake_token_mint: Pubkey,
+    pub total_staked_amount: u64,
+    pub reward_pool_amount: u64,
+    pub owed_amount: u64,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct StakeAccountMigrated {
+    pub vault: Pubkey,
+    pub legacy_vault: Pubkey,
+    pub staker: Pubkey,
+    pub staked_amount: u64,
+    pub earned_amount: u64,
+    pub total_user_count: u64,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct StakeAccountClosed {
+    pub vault: Pubkey,
+    pub staker: Pubkey,
+    pub recipient: Pubkey,
+    pub total_user_count: u64,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct VaultClosed {
+    pub vault: Pubkey,
+    pub fee_vault: Pubkey,
+    pub authority: Pubkey,
+    pub recipient: Pubkey,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct OrphanedStakeAccountClosed {
+    pub vault: Pubkey,
+    pub staker: Pubkey,
+    pub recipient: Pubkey,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct OrphanedReferrerAccountClosed {
+    pub vault: Pubkey,
+    pub referrer: Pubkey,
+    pub recipient: Pubkey,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct FeeVaultUpdateQueued {
+    pub fee_vault: Pubkey,
+    pub fee_wallet: Pubkey,
+    pub stake_fee: u64,
+    pub unstake_fee: u64,
+    pub stake_fee_bps: u16,
+    pub unstake_fee_bps: u16,
+    pub performance_fee_bps: u16,
+    pub effective_at: u64,
+    pub timestamp: u64,
+}
+
+#[event]
+pub struct FeeVaultUpdated {
+    pub fee_vault: Pubkey,
+    pub fee_wallet: Pubkey,
+    pub stake_fee: u64,
+    pub unstake_fee: u64,
+    pub stake_fee_bps: u16,
+    pub unstake_fee_bps: u16,
+    pub performance_fee_bps: u16,
+    pub timestamp: u64,
+}
@@ -2,13 +2,11 @@ use crate::state::*;
 use anchor_lang::prelude::*;
 use anchor_spl::{
     associated_token::AssociatedToken,
-    mint::USDC,
     token::{Mint, Token, TokenAccount},
 };
 use emperor_staking::program::EmperorStaking;
 
 #[derive(Accounts)]
-#[instruction(bump: u8)]
 pub struct InitializeVault<'info> {
     #[account(mut)]
     pub authority: Signer<'info>,
@@ -19,6 +17,7 @@ pub struct InitializeVault<'info> {
     #[account(
         seeds = [
             b"vault".as_ref(),
+            vault.key().as_ref(),
         ],
         bump,
     )]
@@ -34,6 +33,16 @@ pub struct InitializeVault<'info> {
     )]
     pub vault_ata: Box<Account<'info, TokenAccount>>,
 
+    pub reward_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        associated_token::authority = token_vault,
+        associated_token::mint = reward_mint,
+    )]
+    pub reward_vault_ata: Box<Account<'info, TokenAccount>>,
+
     pub system_program: Program<'info, System>,
 
     pub token_program: Program<'info, Token>,
@@ -43,6 +52,84 @@ pub struct InitializeVault<'info> {
     pub rent: Sysvar<'info, Rent>,
 }
 
+#[derive(Accounts)]
+pub struct MigrateVault<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// CHECK: still in the legacy layout, checked and read by the handler.
+    #[account(mut, owner = crate::ID)]
+    pub legacy_vault: UncheckedAccount<'info>,
+
+    #[account(zero)]
+    pub vault: AccountLoader<'info, Vault>,
+
+    #[account(
+        seeds = [
+            b"vault".as_ref(),
+            vault.key().as_ref(),
+        ],
+        bump,
+    )]
+    pub token_vault: SystemAccount<'info>,
+
+    /// CHECK: the signer all legacy vaults shared, checked against the legacy bump by the handler.
+    pub legacy_token_vault: UncheckedAccount<'info>,
+
+    pub stake_token_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        mut,
+        associated_token::authority = legacy_token_vault,
+        associated_token::mint = stake_token_mint,
+    )]
+    pub legacy_vault_ata: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        associated_token::authority = token_vault,
+        associated_token::mint = stake_token_mint,
+    )]
+    pub vault_ata: Box<Account<'info, TokenAccount>>,
+
+    pub system_program: Program<'info, System>,
+
+    pub token_program: Program<'info, Token>,
+
+    pub associated_token_program: Program<'info, AssociatedToken>,
+
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateStakeAccount<'info> {
+    #[account(mut)]
+    pub staker: Signer<'info>,
+
+    #[account(mut)]
+    pub vault: AccountLoader<'info, Vault>,
+
+    /// CHECK: the legacy vault `vault` was migrated from, checked and read by the handler.
+    #[account(mut, address = vault.load()?.legacy_vault)]
+    pub legacy_vault: UncheckedAccount<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = staker,
+        space = User::LEN + 8,
+        seeds = [
+            b"user".as_ref(),
+            vault.key().as_ref(),
+            staker.key().as_ref(),
+        ],
+        bump,
+    )]
+    pub user: Box<Account<'info, User>>,
+
+    pub system_program: Program<'info, System>,
+}
+
 #[derive(Accounts)]
 pub struct UpdateVault<'info> {
     #[account(mut, address = vault.load()?.authority)]
@@ -54,6 +141,7 @@ pub struct UpdateVault<'info> {
     #[account(
         seeds = [
             b"vault".as_ref(),
+            vault.key().as_ref(),
         ],
         bump = vault.load()?.bump,
     )]
@@ -79,8 +167,89 @@ pub struct UpdateVault<'info> {
 }
 
 #[derive(Accounts)]
-pub struct Fund<'info> {
+pub struct SetPaused<'info> {
+    #[account(address = vault.load()?.pauser)]
+    pub pauser: Signer<'info>,
+
+    #[account(mut)]
+    pub vault: AccountLoader<'info, Vault>,
+}
+
+#[derive(Accounts)]
+pub struct SetRoles<'info> {
+    #[account(address = vault.load()?.authority)]
+    pub authority: Signer<'info>,
+
+    #[account(mut)]
+    pub vault: AccountLoader<'info, Vault>,
+}
+
+#[derive(Accounts)]
+pub struct SetReferralShare<'info> {
+    #[account(address = vault.load()?.rewards_manager)]
+    pub rewards_manager: Signer<'info>,
+
+    #[account(mut)]
+    pub vault: AccountLoader<'info, Vault>,
+}
+
+#[derive(Accounts)]
+pub struct ProposeAuthority<'info> {
+    #[account(address = vault.load()?.authority)]
+    pub authority: Signer<'info>,
+
     #[account(mut)]
+    pub vault: AccountLoader<'info, Vault>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptAuthority<'info> {
+    #[account(address = vault.load()?.pending_authority)]
+    pub pending_authority: Signer<'info>,
+
+    #[account(mut)]
+    pub vault: AccountLoader<'info, Vault>,
+}
+
+#[derive(Accounts)]
+pub struct AddReward<'info> {
+    #[account(mut, address = vault.load()?.rewards_manager)]
+    pub authority: Signer<'info>,
+
+    #[account(mut)]
+    pub vault: AccountLoader<'info, Vault>,
+
+    #[account(
+        seeds = [
+            b"vault".as_ref(),
+            vault.key().as_ref(),
+        ],
+        bump = vault.load()?.bump,
+    )]
+    pub token_vault: SystemAccount<'info>,
+
+    pub reward_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        associated_token::authority = token_vault,
+        associated_token::mint = reward_mint,
+    )]
+    pub reward_vault_ata: Box<Account<'info, TokenAccount>>,
+
+    pub system_program: Program<'info, System>,
+
+    pub token_program: Program<'info, Token>,
+
+    pub associated_token_program: Program<'info, AssociatedToken>,
+
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct Fund<'info> {
+    #[account(mut, address = vault.load()?.rewards_manager)]
     pub funder: Signer<'info>,
 
     #[account(mut)]
@@ -89,18 +258,18 @@ pub struct Fund<'info> {
     #[account(
         seeds = [
             b"vault".as_ref(),
+            vault.key().as_ref(),
         ],
         bump = vault.load()?.bump,
     )]
     pub token_vault: SystemAccount<'info>,
 
-    #[account(address = USDC)]
-    pub usdc_mint: Account<'info, Mint>,
+    pub reward_mint: Account<'info, Mint>,
 
     #[account(
         mut,
         associated_token::authority = funder,
-        associated_token::mint = usdc_mint,
+        associated_token::mint = reward_mint,
     )]
     pub funder_ata: Account<'info, TokenAccount>,
 
@@ -108,7 +277,7 @@ pub struct Fund<'info> {
         init_if_needed,
         payer = funder,
         associated_token::authority = token_vault,
-        associated_token::mint = usdc_mint,
+        associated_token::mint = reward_mint,
     )]
     pub vault_ata: Account<'info, TokenAccount>,
 
@@ -123,7 +292,7 @@ pub struct Fund<'info> {
 
 #[derive(Accounts)]
 pub struct Withdraw<'info> {
-    #[account(mut, address = vault.load()?.authority)]
+    #[account(mut, address = vault.load()?.rewards_manager)]
     pub authority: Signer<'info>,
 
     #[account(mut)]
@@ -132,22 +301,25 @@ pub struct Withdraw<'info> {
     #[account(
         seeds = [
             b"vault".as_ref(),
+            vault.key().as_ref(),
         ],
         bump = vault.load()?.bump,
     )]
     pub token_vault: SystemAccount<'info>,
 
+    pub reward_mint: Account<'info, Mint>,
+
     #[account(
         mut,
         associated_token::authority = authority,
-        associated_token::mint = USDC,
+        associated_token::mint = reward_mint,
     )]
     pub authority_ata: Account<'info, TokenAccount>,
 
     #[account(
         mut,
         associated_token::authority = token_vault,
-        associated_token::mint = USDC,
+        associated_token::mint = reward_mint,
     )]
     pub vault_ata: Account<'info, TokenAccount>,
 
@@ -162,6 +334,19 @@ pub struct Stake<'info> {
     #[account(mut)]
     pub vault: AccountLoader<'info, Vault>,
 
+    #[account(
+        init_if_needed,
+        payer = staker,
+        space = User::LEN + 8,
+        seeds = [
+            b"user".as_ref(),
+            vault.key().as_ref(),
+            staker.key().as_ref(),
+        ],
+        bump,
+    )]
+    pub user: Box<Account<'info, User>>,
+
     #[account(
         mut,
         seeds =[
@@ -179,6 +364,7 @@ pub struct Stake<'info> {
     #[account(
         seeds = [
             b"vault".as_ref(),
+            vault.key().as_ref(),
         ],
         bump = vault.load()?.bump,
     )]
@@ -198,6 +384,10 @@ pub struct Stake<'info> {
     )]
     pub vault_ata: Account<'info, TokenAccount>,
 
+    /// CHECK: only read when there is a fee to send, checked against the mint and fee wallet then.
+    #[account(mut)]
+    pub fee_token_account: UncheckedAccount<'info>,
+
     pub system_program: Program<'info, System>,
 
     pub token_program: Program<'info, Token>,
@@ -229,6 +419,19 @@ pub struct StakeWithClaim<'info> {
     #[account(mut)]
     pub vault: AccountLoader<'info, Vault>,
 
+    #[account(
+        init_if_needed,
+        payer = staker,
+        space = User::LEN + 8,
+        seeds = [
+            b"user".as_ref(),
+            vault.key().as_ref(),
+            staker.key().as_ref(),
+        ],
+        bump,
+    )]
+    pub user: Box<Account<'info, User>>,
+
     #[account(
         mut,
         seeds =[
@@ -246,6 +449,7 @@ pub struct StakeWithClaim<'info> {
     #[account(
         seeds = [
             b"vault".as_ref(),
+            vault.key().as_ref(),
         ],
         bump = vault.load()?.bump,
     )]
@@ -265,6 +469,10 @@ pub struct StakeWithClaim<'info> {
     )]
     pub vault_ata: Box<Account<'info, TokenAccount>>,
 
+    /// CHECK: only read when there is a fee to send, checked against the mint and fee wallet then.
+    #[account(mut)]
+    pub fee_token_account: UncheckedAccount<'info>,
+
     pub emperor_program: Program<'info, EmperorStaking>,
 
     pub system_program: Program<'info, System>,
@@ -284,6 +492,17 @@ pub struct Unstake<'info> {
     #[account(mut)]
     pub vault: AccountLoader<'info, Vault>,
 
+    #[account(
+        mut,
+        seeds = [
+            b"user".as_ref(),
+            vault.key().as_ref(),
+            staker.key().as_ref(),
+        ],
+        bump = user.bump,
+    )]
+    pub user: Box<Account<'info, User>>,
+
     #[account(
         mut,
         seeds =[
@@ -298,9 +517,58 @@ pub struct Unstake<'info> {
     #[account(mut, address = fee_vault.fee_wallet)]
     pub fee_wallet: SystemAccount<'info>,
 
+    // Mutable so a burned early-exit penalty can reduce its supply.
+    #[account(mut, address = vault.load()?.stake_token_mint)]
+    pub stake_token_mint: Box<Account<'info, Mint>>,
+
     #[account(
         seeds = [
             b"vault".as_ref(),
+            vault.key().as_ref(),
+        ],
+        bump = vault.load()?.bump,
+    )]
+    pub token_vault: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        associated_token::authority = token_vault,
+        associated_token::mint = vault.load()?.stake_token_mint,
+    )]
+    pub vault_ata: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: only read when there is a fee to send, checked against the mint and fee wallet then.
+    #[account(mut)]
+    pub fee_token_account: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawUnbonded<'info> {
+    #[account(mut)]
+    pub staker: Signer<'info>,
+
+    #[account(mut)]
+    pub vault: AccountLoader<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"user".as_ref(),
+            vault.key().as_ref(),
+            staker.key().as_ref(),
+        ],
+        bump = user.bump,
+    )]
+    pub user: Box<Account<'info, User>>,
+
+    #[account(
+        seeds = [
+            b"vault".as_ref(),
+            vault.key().as_ref(),
         ],
         bump = vault.load()?.bump,
     )]
@@ -320,11 +588,26 @@ pub struct Unstake<'info> {
     )]
     pub vault_ata: Account<'info, TokenAccount>,
 
-    pub system_program: Program<'info, System>,
-
     pub token_program: Program<'info, Token>,
 }
 
+#[derive(Accounts)]
+pub struct ExpireLock<'info> {
+    #[account(mut)]
+    pub vault: AccountLoader<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"user".as_ref(),
+            vault.key().as_ref(),
+            user.key.as_ref(),
+        ],
+        bump = user.bump,
+    )]
+    pub user: Box<Account<'info, User>>,
+}
+
 #[derive(Accounts)]
 pub struct Claim<'info> {
     #[account(mut)]
@@ -333,21 +616,41 @@ pub struct Claim<'info> {
     #[account(mut)]
     pub vault: AccountLoader<'info, Vault>,
 
+    #[account(
+        mut,
+        seeds = [
+            b"user".as_ref(),
+            vault.key().as_ref(),
+            staker.key().as_ref(),
+        ],
+        bump = user.bump,
+    )]
+    pub user: Box<Account<'info, User>>,
+
     #[account(
         seeds = [
             b"vault".as_ref(),
+            vault.key().as_ref(),
         ],
         bump = vault.load()?.bump,
     )]
     pub token_vault: SystemAccount<'info>,
 
-    #[account(address = USDC)]
-    pub usdc_mint: Account<'info, Mint>,
+    #[account(
+        seeds =[
+            b"fee-vault".as_ref(),
+            vault.key().as_ref(),
+        ],
+        bump = fee_vault.bump,
+    )]
+    pub fee_vault: Box<Account<'info, FeeVault>>,
+
+    pub reward_mint: Account<'info, Mint>,
 
     #[account(
         mut,
         associated_token::authority = token_vault,
-        associated_token::mint = usdc_mint,
+        associated_token::mint = reward_mint,
     )]
     pub vault_ata: Account<'info, TokenAccount>,
 
@@ -355,10 +658,14 @@ pub struct Claim<'info> {
         init_if_needed,
         payer = staker,
         associated_token::authority = staker,
-        associated_token::mint = usdc_mint,
+        associated_token::mint = reward_mint,
     )]
     pub staker_ata: Account<'info, TokenAccount>,
 
+    /// CHECK: only read when there is a fee to send, checked against the mint and fee wallet then.
+    #[account(mut)]
+    pub fee_token_account: UncheckedAccount<'info>,
+
     pub system_program: Program<'info, System>,
 
     pub token_program: Program<'info, Token>,
@@ -369,20 +676,237 @@ pub struct Claim<'info> {
 }
 
 #[derive(Accounts)]
-pub struct ClosePda<'info> {
-    #[account(mut, address = "3qWq2ehELrVJrTg2JKKERm67cN6vYjm1EyhCEzfQ6jMd".parse::<Pubkey>().unwrap())]
+pub struct ClaimReferral<'info> {
+    #[account(mut)]
+    pub referrer: Signer<'info>,
+
+    #[account(mut)]
+    pub vault: AccountLoader<'info, Vault>,
+
+    #[account(
+        init_if_needed,
+        payer = referrer,
+        space = Referrer::LEN + 8,
+        seeds = [
+            b"referrer".as_ref(),
+            vault.key().as_ref(),
+            referrer.key().as_ref(),
+        ],
+        bump,
+    )]
+    pub referrer_account: Box<Account<'info, Referrer>>,
+
+    #[account(
+        seeds = [
+            b"vault".as_ref(),
+            vault.key().as_ref(),
+        ],
+        bump = vault.load()?.bump,
+    )]
+    pub token_vault: SystemAccount<'info>,
+
+    pub reward_mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        associated_token::authority = token_vault,
+        associated_token::mint = reward_mint,
+    )]
+    pub vault_ata: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        init_if_needed,
+        payer = referrer,
+        associated_token::authority = referrer,
+        associated_token::mint = reward_mint,
+    )]
+    pub referrer_ata: Box<Account<'info, TokenAccount>>,
+
+    pub system_program: Program<'info, System>,
+
+    pub token_program: Program<'info, Token>,
+
+    pub associated_token_program: Program<'info, AssociatedToken>,
+
+    pub rent: Sysvar<'info, Rent>,
+    // Remaining accounts: the writable `User` account of every referee to claim from.
+}
+
+#[derive(Accounts)]
+pub struct ClaimAll<'info> {
+    #[account(mut)]
+    pub staker: Signer<'info>,
+
+    #[account(mut)]
+    pub vault: AccountLoader<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"user".as_ref(),
+            vault.key().as_ref(),
+            staker.key().as_ref(),
+        ],
+        bump = user.bump,
+    )]
+    pub user: Box<Account<'info, User>>,
+
+    #[account(
+        seeds =[
+            b"fee-vault".as_ref(),
+            vault.key().as_ref(),
+        ],
+        bump = fee_vault.bump,
+    )]
+    pub fee_vault: Box<Account<'info, FeeVault>>,
+
+    #[account(
+        seeds = [
+            b"vault".as_ref(),
+            vault.key().as_ref(),
+        ],
+        bump = vault.load()?.bump,
+    )]
+    pub token_vault: SystemAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+    // Remaining accounts: the vault ATA, staker ATA and fee wallet token account of every
+    // reward, in `Vault::rewards` order.
+}
+
+#[derive(Accounts)]
+pub struct Compound<'info> {
     pub signer: Signer<'info>,
 
-    /// CHECK:
     #[account(mut)]
-    pub pda: AccountInfo<'info>,
+    pub vault: AccountLoader<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"user".as_ref(),
+            vault.key().as_ref(),
+            user.key.as_ref(),
+        ],
+        bump = user.bump,
+    )]
+    pub user: Box<Account<'info, User>>,
+
+    #[account(
+        seeds =[
+            b"fee-vault".as_ref(),
+            vault.key().as_ref(),
+        ],
+        bump = fee_vault.bump,
+    )]
+    pub fee_vault: Box<Account<'info, FeeVault>>,
+
+    #[account(
+        seeds = [
+            b"vault".as_ref(),
+            vault.key().as_ref(),
+        ],
+        bump = vault.load()?.bump,
+    )]
+    pub token_vault: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        associated_token::authority = token_vault,
+        associated_token::mint = vault.load()?.stake_token_mint,
+    )]
+    pub vault_ata: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: only read when there is a fee to send, checked against the mint and fee wallet then.
+    #[account(mut)]
+    pub fee_token_account: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct SetAutoCompound<'info> {
+    pub staker: Signer<'info>,
+
+    pub vault: AccountLoader<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"user".as_ref(),
+            vault.key().as_ref(),
+            staker.key().as_ref(),
+        ],
+        bump = user.bump,
+    )]
+    pub user: Box<Account<'info, User>>,
+}
+
+#[derive(Accounts)]
+pub struct CloseStakeAccount<'info> {
+    // Pays for the referrer's account if referral rewards have to be moved to it.
+    #[account(mut)]
+    pub staker: Signer<'info>,
+
+    #[account(mut)]
+    pub vault: AccountLoader<'info, Vault>,
+
+    #[account(
+        mut,
+        close = recipient,
+        seeds = [
+            b"user".as_ref(),
+            vault.key().as_ref(),
+            staker.key().as_ref(),
+        ],
+        bump = user.bump,
+    )]
+    pub user: Box<Account<'info, User>>,
+
+    #[account(mut)]
+    pub recipient: SystemAccount<'info>,
 
     pub system_program: Program<'info, System>,
 }
 
+#[derive(Accounts)]
+pub struct ExecutePendingUpdate<'info> {
+    #[account(mut)]
+    pub vault: AccountLoader<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds =[
+            b"fee-vault".as_ref(),
+            vault.key().as_ref(),
+        ],
+        bump = fee_vault.bump,
+    )]
+    pub fee_vault: Account<'info, FeeVault>,
+}
+
+#[derive(Accounts)]
+pub struct CancelPendingUpdate<'info> {
+    #[account(address = vault.load()?.authority)]
+    pub authority: Signer<'info>,
+
+    #[account(mut)]
+    pub vault: AccountLoader<'info, Vault>,
+
+    #[account(
+        mut,
+        seeds =[
+            b"fee-vault".as_ref(),
+            vault.key().as_ref(),
+        ],
+        bump = fee_vault.bump,
+    )]
+    pub fee_vault: Account<'info, FeeVault>,
+}
+
 #[derive(Accounts)]
 pub struct InitializeFeeVault<'info> {
-    #[account(mut, address = vault.load()?.authority)]
+    #[account(mut, address = vault.load()?.fee_manager)]
     pub authority: Signer<'info>,
 
     #[account(mut)]
@@ -406,7 +930,7 @@ pub struct InitializeFeeVault<'info> {
 
 #[derive(Accounts)]
 pub struct UpdateFeeVault<'info> {
-    #[account(mut, address = vault.load()?.authority)]
+    #[account(mut, address = vault.load()?.fee_manager)]
     pub authority: Signer<'info>,
 
     #[account(mut)]
@@ -423,3 +947,74 @@ pub struct UpdateFeeVault<'info> {
     )]
     pub fee_vault: Account<'info, FeeVault>,
 }
+
+#[derive(Accounts)]
+pub struct CloseVault<'info> {
+    #[account(address = vault.load()?.authority)]
+    pub authority: Signer<'info>,
+
+    #[account(mut, close = recipient)]
+    pub vault: AccountLoader<'info, Vault>,
+
+    /// CHECK: may never have been initialized, closed by the handler if it was.
+    #[account(
+        mut,
+        seeds =[
+            b"fee-vault".as_ref(),
+            vault.key().as_ref(),
+        ],
+        bump,
+    )]
+    pub fee_vault: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub recipient: SystemAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseOrphanedStakeAccount<'info> {
+    pub staker: Signer<'info>,
+
+    /// CHECK: only its address is used, the handler checks it has been closed.
+    pub vault: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        close = recipient,
+        has_one = vault,
+        seeds = [
+            b"user".as_ref(),
+            vault.key().as_ref(),
+            staker.key().as_ref(),
+        ],
+        bump = user.bump,
+    )]
+    pub user: Box<Account<'info, User>>,
+
+    #[account(mut)]
+    pub recipient: SystemAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseOrphanedReferrerAccount<'info> {
+    pub referrer: Signer<'info>,
+
+    /// CHECK: only its address is used, the handler checks it has been closed.
+    pub vault: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        close = recipient,
+        has_one = vault,
+        seeds = [
+            b"referrer".as_ref(),
+            vault.key().as_ref(),
+            referrer.key().as_ref(),
+        ],
+        bump = referrer_account.bump,
+    )]
+    pub referrer_account: Box<Account<'info, Referrer>>,
+
+    #[account(mut)]
+    pub recipient: SystemAccount<'info>,
+}
@@ -1,9 +1,17 @@
+mod errors;
+mod events;
 mod ins;
 mod state;
 
+use crate::errors::*;
+use crate::events::*;
 use crate::ins::*;
-use anchor_lang::{prelude::*, system_program};
-use anchor_spl::token::{transfer, Transfer};
+use crate::state::{
+    bps_amount, now, FeeVault, LegacyUser, LegacyVault, PendingFeeUpdate, PendingVaultUpdate,
+    Referrer, User, Vault, BPS_DENOMINATOR, PENALTY_BURN, PENALTY_REDISTRIBUTE,
+};
+use anchor_lang::{prelude::*, system_program, AccountsClose, Discriminator};
+use anchor_spl::token::{burn, transfer, Burn, TokenAccount, Transfer};
 use emperor_staking::cpi::{accounts::Claim as ClaimJewels, claim as claim_jewels};
 use emperor_staking::{self};
 
@@ -14,35 +22,383 @@ pub mod spl_staking {
 
     use super::*;
 
-    pub fn initialize_vault(
-        ctx: Context<InitializeVault>,
-        daily_payout_amount: u64,
-        bump: u8,
-    ) -> Result<()> {
+    pub fn initialize_vault(ctx: Context<InitializeVault>) -> Result<()> {
         let mut vault = ctx.accounts.vault.load_init()?;
-        vault.bump = bump;
+        let timestamp = now()?;
+        vault.bump = *ctx
+            .bumps
+            .get("token_vault")
+            .ok_or(CustomError::BumpNotFound)?;
         vault.stake_token_mint = ctx.accounts.stake_token_mint.key();
-        vault.daily_payout_amount = daily_payout_amount;
         vault.authority = ctx.accounts.authority.key();
+        vault.fee_manager = vault.authority;
+        vault.rewards_manager = vault.authority;
+        vault.pauser = vault.authority;
+        vault.add_reward(timestamp, ctx.accounts.reward_mint.key())?;
+
+        emit!(VaultInitialized {
+            vault: ctx.accounts.vault.key(),
+            authority: vault.authority,
+            stake_token_mint: vault.stake_token_mint,
+            reward_mint: ctx.accounts.reward_mint.key(),
+            timestamp,
+        });
+
+        Ok(())
+    }
+
+    /*
+     * Replace a legacy vault, which kept every staker in one account, with a vault of this layout.
+     * Its tokens move to the new vault's ATA and each staker claims its position with
+     * `migrate_stake_account`.
+     */
+    pub fn migrate_vault(ctx: Context<MigrateVault>) -> Result<()> {
+        let mut vault = ctx.accounts.vault.load_init()?;
+        let timestamp = now()?;
+
+        let (legacy, owed_amount, legacy_bump) = {
+            let mut data = ctx.accounts.legacy_vault.try_borrow_mut_data()?;
+            require!(
+                data.len() == LegacyVault::LEN && data[..8] == Vault::discriminator(),
+                CustomError::InvalidLegacyVault
+            );
+            let legacy = LegacyVault::header(&data)?;
+            require_keys_eq!(
+                legacy.authority,
+                ctx.accounts.authority.key(),
+                CustomError::Unauthorized
+            );
+            require_keys_eq!(
+                legacy.stake_token_mint,
+                ctx.accounts.stake_token_mint.key(),
+                CustomError::InvalidTokenAccount
+            );
+
+            let owed_amount = legacy.settle(&mut data, timestamp)?;
+            let legacy_bump = LegacyVault::bump(&data);
+            data[..8].copy_from_slice(&LegacyVault::MIGRATED_DISCRIMINATOR);
+            (legacy, owed_amount, legacy_bump)
+        };
+
+        vault.bump = *ctx
+            .bumps
+            .get("token_vault")
+            .ok_or(CustomError::BumpNotFound)?;
+        vault.authority = legacy.authority;
+        vault.fee_manager = vault.authority;
+        vault.rewards_manager = vault.authority;
+        vault.pauser = vault.authority;
+        vault.stake_token_mint = legacy.stake_token_mint;
+        vault.legacy_vault = ctx.accounts.legacy_vault.key();
+        vault.migrate(timestamp, &legacy, owed_amount)?;
+
+        emit!(VaultMigrated {
+            vault: ctx.accounts.vault.key(),
+            legacy_vault: vault.legacy_vault,
+            authority: vault.authority,
+            stake_token_mint: vault.stake_token_mint,
+            total_staked_amount: vault.total_staked_amount,
+            reward_pool_amount: vault.rewards[0].reward_pool_amount,
+            owed_amount,
+            timestamp,
+        });
+
+        // Every legacy vault shared this signer and ATA, so only what this one accounts for moves.
+        let seeds = [b"vault".as_ref(), &[legacy_bump]];
+        let legacy_token_vault = Pubkey::create_program_address(&seeds, &crate::ID)
+            .map_err(|_| CustomError::InvalidLegacyVault)?;
+        require_keys_eq!(
+            legacy_token_vault,
+            ctx.accounts.legacy_token_vault.key(),
+            CustomError::InvalidLegacyVault
+        );
+        let amount = legacy
+            .total_staked_amount
+            .checked_add(legacy.reward_pool_amount)
+            .ok_or(CustomError::MathOverflow)?;
+        transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.legacy_vault_ata.to_account_info(),
+                    to: ctx.accounts.vault_ata.to_account_info(),
+                    authority: ctx.accounts.legacy_token_vault.to_account_info(),
+                },
+                &[&seeds[..]],
+            ),
+            amount,
+        )?;
 
         Ok(())
     }
 
     pub fn update_vault(
         ctx: Context<UpdateVault>,
-        new_authority: Pubkey,
-        daily_payout_amount: u64,
+        unbonding_period: u64,
+        update_delay: u64,
+        penalty_period: u64,
+        penalty_bps: u16,
+        penalty_mode: u8,
     ) -> Result<()> {
         let mut vault = ctx.accounts.vault.load_mut()?;
-        vault.stake_token_mint = ctx.accounts.stake_token_mint.key();
-        vault.daily_payout_amount = daily_payout_amount;
-        vault.authority = new_authority;
+        let timestamp = now()?;
+
+        require!(
+            penalty_bps as u64 <= BPS_DENOMINATOR
+                && (penalty_mode == PENALTY_REDISTRIBUTE || penalty_mode == PENALTY_BURN),
+            CustomError::InvalidPenalty
+        );
+        require!(
+            vault.can_set_stake_token_mint(ctx.accounts.stake_token_mint.key()),
+            CustomError::StakeMintInUse
+        );
+        // Redistributed penalties are paid out through the stake mint's own reward stream.
+        if penalty_bps > 0 && penalty_mode == PENALTY_REDISTRIBUTE {
+            vault.reward_index(ctx.accounts.stake_token_mint.key())?;
+        }
+
+        // Queued behind the current delay, so stakers see it coming. Queuing again replaces it.
+        let effective_at = timestamp
+            .checked_add(vault.update_delay)
+            .ok_or(CustomError::MathOverflow)?;
+        vault.pending_update = PendingVaultUpdate {
+            stake_token_mint: ctx.accounts.stake_token_mint.key(),
+            unbonding_period,
+            update_delay,
+            penalty_period,
+            penalty_bps,
+            penalty_mode,
+            effective_at,
+        };
+
+        emit!(VaultUpdateQueued {
+            vault: ctx.accounts.vault.key(),
+            stake_token_mint: vault.pending_update.stake_token_mint,
+            unbonding_period,
+            update_delay,
+            penalty_period,
+            penalty_bps,
+            penalty_mode,
+            effective_at,
+            timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn execute_pending_update(ctx: Context<ExecutePendingUpdate>) -> Result<()> {
+        let mut vault = ctx.accounts.vault.load_mut()?;
+        let fee_vault = &mut ctx.accounts.fee_vault;
+        let timestamp = now()?;
+
+        let vault_update = vault.pending_update;
+        let fee_update = fee_vault.pending_update;
+        require!(
+            vault_update.effective_at != 0 || fee_update.effective_at != 0,
+            CustomError::NoPendingUpdate
+        );
+
+        let mut executed = false;
+        if vault_update.effective_at != 0 && vault_update.effective_at <= timestamp {
+            // Stake may have come in since the change was queued.
+            require!(
+                vault.can_set_stake_token_mint(vault_update.stake_token_mint),
+                CustomError::StakeMintInUse
+            );
+            vault.stake_token_mint = vault_update.stake_token_mint;
+            vault.unbonding_period = vault_update.unbonding_period;
+            vault.update_delay = vault_update.update_delay;
+            vault.penalty_period = vault_update.penalty_period;
+            vault.penalty_bps = vault_update.penalty_bps;
+            vault.penalty_mode = vault_update.penalty_mode;
+            vault.pending_update = PendingVaultUpdate::default();
+            executed = true;
+
+            emit!(VaultUpdated {
+                vault: ctx.accounts.vault.key(),
+                stake_token_mint: vault.stake_token_mint,
+                unbonding_period: vault.unbonding_period,
+                update_delay: vault.update_delay,
+                penalty_period: vault.penalty_period,
+                penalty_bps: vault.penalty_bps,
+                penalty_mode: vault.penalty_mode,
+                timestamp,
+            });
+        }
+        if fee_update.effective_at != 0 && fee_update.effective_at <= timestamp {
+            fee_vault.fee_wallet = fee_update.fee_wallet;
+            fee_vault.stake_fee = fee_update.stake_fee;
+            fee_vault.unstake_fee = fee_update.unstake_fee;
+            fee_vault.stake_fee_bps = fee_update.stake_fee_bps;
+            fee_vault.unstake_fee_bps = fee_update.unstake_fee_bps;
+            fee_vault.performance_fee_bps = fee_update.performance_fee_bps;
+            fee_vault.pending_update = PendingFeeUpdate::default();
+            executed = true;
+
+            emit!(FeeVaultUpdated {
+                fee_vault: fee_vault.key(),
+                fee_wallet: fee_vault.fee_wallet,
+                stake_fee: fee_vault.stake_fee,
+                unstake_fee: fee_vault.unstake_fee,
+                stake_fee_bps: fee_vault.stake_fee_bps,
+                unstake_fee_bps: fee_vault.unstake_fee_bps,
+                performance_fee_bps: fee_vault.performance_fee_bps,
+                timestamp,
+            });
+        }
+        require!(executed, CustomError::UpdateNotReady);
+
+        Ok(())
+    }
+
+    pub fn cancel_pending_update(ctx: Context<CancelPendingUpdate>) -> Result<()> {
+        let mut vault = ctx.accounts.vault.load_mut()?;
+        let fee_vault = &mut ctx.accounts.fee_vault;
+        require!(
+            vault.pending_update.effective_at != 0 || fee_vault.pending_update.effective_at != 0,
+            CustomError::NoPendingUpdate
+        );
+
+        vault.pending_update = PendingVaultUpdate::default();
+        fee_vault.pending_update = PendingFeeUpdate::default();
+
+        emit!(PendingUpdateCancelled {
+            vault: ctx.accounts.vault.key(),
+            fee_vault: fee_vault.key(),
+            timestamp: now()?,
+        });
+
+        Ok(())
+    }
+
+    pub fn set_roles(
+        ctx: Context<SetRoles>,
+        fee_manager: Pubkey,
+        rewards_manager: Pubkey,
+        pauser: Pubkey,
+    ) -> Result<()> {
+        let mut vault = ctx.accounts.vault.load_mut()?;
+        vault.fee_manager = fee_manager;
+        vault.rewards_manager = rewards_manager;
+        vault.pauser = pauser;
+
+        emit!(RolesUpdated {
+            vault: ctx.accounts.vault.key(),
+            fee_manager,
+            rewards_manager,
+            pauser,
+            timestamp: now()?,
+        });
+
+        Ok(())
+    }
+
+    pub fn set_referral_share(ctx: Context<SetReferralShare>, referral_bps: u16) -> Result<()> {
+        require!(
+            referral_bps as u64 <= BPS_DENOMINATOR,
+            CustomError::InvalidReferralShare
+        );
+
+        let mut vault = ctx.accounts.vault.load_mut()?;
+        vault.referral_bps = referral_bps;
+
+        emit!(ReferralShareSet {
+            vault: ctx.accounts.vault.key(),
+            referral_bps,
+            timestamp: now()?,
+        });
+
+        Ok(())
+    }
+
+    pub fn propose_authority(ctx: Context<ProposeAuthority>, new_authority: Pubkey) -> Result<()> {
+        let mut vault = ctx.accounts.vault.load_mut()?;
+        vault.pending_authority = new_authority;
+
+        emit!(AuthorityProposed {
+            vault: ctx.accounts.vault.key(),
+            authority: vault.authority,
+            pending_authority: new_authority,
+            timestamp: now()?,
+        });
+
+        Ok(())
+    }
+
+    pub fn cancel_authority(ctx: Context<ProposeAuthority>) -> Result<()> {
+        let mut vault = ctx.accounts.vault.load_mut()?;
+        let pending_authority = vault.pending_authority;
+        require_keys_neq!(
+            pending_authority,
+            Pubkey::default(),
+            CustomError::NoPendingAuthority
+        );
+        vault.pending_authority = Pubkey::default();
+
+        emit!(AuthorityProposalCancelled {
+            vault: ctx.accounts.vault.key(),
+            authority: vault.authority,
+            pending_authority,
+            timestamp: now()?,
+        });
+
+        Ok(())
+    }
+
+    pub fn accept_authority(ctx: Context<AcceptAuthority>) -> Result<()> {
+        let mut vault = ctx.accounts.vault.load_mut()?;
+        let old_authority = vault.accept_authority();
+        let new_authority = vault.authority;
+
+        emit!(AuthorityChanged {
+            vault: ctx.accounts.vault.key(),
+            old_authority,
+            new_authority,
+            timestamp: now()?,
+        });
+        emit!(RolesUpdated {
+            vault: ctx.accounts.vault.key(),
+            fee_manager: vault.fee_manager,
+            rewards_manager: vault.rewards_manager,
+            pauser: vault.pauser,
+            timestamp: now()?,
+        });
+
+        Ok(())
+    }
+
+    pub fn set_paused(ctx: Context<SetPaused>, paused: bool) -> Result<()> {
+        let mut vault = ctx.accounts.vault.load_mut()?;
+        vault.paused = paused;
+
+        emit!(VaultPaused {
+            vault: ctx.accounts.vault.key(),
+            paused,
+            timestamp: now()?,
+        });
 
         Ok(())
     }
 
-    pub fn fund(ctx: Context<Fund>, amount: u64) -> Result<()> {
+    pub fn add_reward(ctx: Context<AddReward>) -> Result<()> {
         let mut vault = ctx.accounts.vault.load_mut()?;
+        let timestamp = now()?;
+        vault.add_reward(timestamp, ctx.accounts.reward_mint.key())?;
+
+        emit!(RewardAdded {
+            vault: ctx.accounts.vault.key(),
+            mint: ctx.accounts.reward_mint.key(),
+            reward_count: vault.reward_count,
+            timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn fund(ctx: Context<Fund>, amount: u64, start_time: u64, end_time: u64) -> Result<()> {
+        let mut vault = ctx.accounts.vault.load_mut()?;
+        require!(!vault.paused, CustomError::Paused);
+        let index = vault.reward_index(ctx.accounts.reward_mint.key())?;
 
         transfer(
             CpiContext::new(
@@ -56,16 +412,50 @@ pub mod spl_staking {
             amount,
         )?;
 
-        vault.reward_pool_amount = vault.reward_pool_amount.checked_add(amount).unwrap();
+        // Settle the running period at its old rate before the new one replaces it.
+        let timestamp = now()?;
+        vault.update(timestamp)?;
+        vault.rewards[index].fund(timestamp, amount, start_time, end_time)?;
+
+        let reward = &vault.rewards[index];
+        emit!(Funded {
+            vault: ctx.accounts.vault.key(),
+            funder: ctx.accounts.funder.key(),
+            mint: reward.mint,
+            amount,
+            period_amount: reward.period_amount,
+            period_start_time: reward.period_start_time,
+            period_end_time: reward.period_end_time,
+            reward_pool_amount: reward.reward_pool_amount,
+            timestamp,
+        });
 
         Ok(())
     }
 
     pub fn withdraw(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
         let mut vault = ctx.accounts.vault.load_mut()?;
+        let timestamp = now()?;
+        let index = vault.reward_index(ctx.accounts.reward_mint.key())?;
         let bump = vault.bump;
         let vault_bump = bump;
-        let seeds = [b"vault".as_ref(), &[vault_bump]];
+
+        // Only what has not been promised to stakers can be taken back.
+        vault.update(timestamp)?;
+        vault.rewards[index].withdraw(amount)?;
+
+        let reward = &vault.rewards[index];
+        emit!(Withdrawn {
+            vault: ctx.accounts.vault.key(),
+            mint: reward.mint,
+            amount,
+            reward_pool_amount: reward.reward_pool_amount,
+            allocated_amount: reward.allocated_amount,
+            timestamp,
+        });
+
+        let vault_key = ctx.accounts.vault.key();
+        let seeds = [b"vault".as_ref(), vault_key.as_ref(), &[vault_bump]];
         let signer = &[&seeds[..]];
         transfer(
             CpiContext::new_with_signer(
@@ -80,13 +470,18 @@ pub mod spl_staking {
             amount,
         )?;
 
-        vault.reward_pool_amount = vault.reward_pool_amount.checked_sub(amount).unwrap();
-
         Ok(())
     }
 
-    pub fn stake(ctx: Context<Stake>, amount: u64) -> Result<()> {
+    pub fn stake(
+        ctx: Context<Stake>,
+        amount: u64,
+        lock_tier: u8,
+        referrer: Option<Pubkey>,
+    ) -> Result<()> {
         let mut vault = ctx.accounts.vault.load_mut()?;
+        let timestamp = now()?;
+        require!(!vault.paused, CustomError::Paused);
         let stake_fee = ctx.accounts.fee_vault.stake_fee;
 
         if stake_fee > 0 {
@@ -102,7 +497,42 @@ pub mod spl_staking {
             )?;
         }
 
-        vault.stake(ctx.accounts.staker.key(), amount);
+        let user = &mut ctx.accounts.user;
+        // Only the first stake of a position can name a referrer.
+        let is_new = vault.open(
+            user,
+            ctx.accounts.vault.key(),
+            ctx.accounts.staker.key(),
+            *ctx.bumps.get("user").ok_or(CustomError::BumpNotFound)?,
+            referrer.unwrap_or_default(),
+        )?;
+        if is_new && user.referrer != Pubkey::default() {
+            emit!(ReferrerSet {
+                vault: ctx.accounts.vault.key(),
+                staker: user.key,
+                referrer: user.referrer,
+                timestamp,
+            });
+        }
+
+        let fee_amount = bps_amount(amount, ctx.accounts.fee_vault.stake_fee_bps);
+        let stake_amount = amount - fee_amount;
+        vault.deposit(timestamp, user, stake_amount, lock_tier)?;
+
+        emit!(Staked {
+            vault: ctx.accounts.vault.key(),
+            staker: user.key,
+            amount,
+            lock_tier,
+            stake_fee,
+            fee_amount,
+            staked_amount: user.staked_amount,
+            weighted_amount: user.weighted_amount,
+            lock_end_time: user.lock_end_time,
+            total_staked_amount: vault.total_staked_amount,
+            total_weighted_amount: vault.total_weighted_amount,
+            timestamp,
+        });
 
         transfer(
             CpiContext::new(
@@ -113,14 +543,35 @@ pub mod spl_staking {
                     authority: ctx.accounts.staker.to_account_info(),
                 },
             ),
-            amount,
+            stake_amount,
         )?;
 
+        if fee_amount > 0 {
+            let fee_token_account = checked_fee_token_account(
+                &ctx.accounts.fee_token_account,
+                ctx.accounts.vault_ata.mint,
+                ctx.accounts.fee_wallet.key(),
+            )?;
+            transfer(
+                CpiContext::new(
+                    ctx.accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: ctx.accounts.staker_ata.to_account_info(),
+                        to: fee_token_account.to_account_info(),
+                        authority: ctx.accounts.staker.to_account_info(),
+                    },
+                ),
+                fee_amount,
+            )?;
+        }
+
         Ok(())
     }
 
     pub fn stake_with_claim(ctx: Context<StakeWithClaim>) -> Result<()> {
         let mut vault = ctx.accounts.vault.load_mut()?;
+        let timestamp = now()?;
+        require!(!vault.paused, CustomError::Paused);
 
         let stake_fee = ctx.accounts.fee_vault.stake_fee;
 
@@ -155,7 +606,32 @@ pub mod spl_staking {
         ))?
         .get();
 
-        vault.stake(ctx.accounts.staker.key(), amount);
+        let user = &mut ctx.accounts.user;
+        vault.open(
+            user,
+            ctx.accounts.vault.key(),
+            ctx.accounts.staker.key(),
+            *ctx.bumps.get("user").ok_or(CustomError::BumpNotFound)?,
+            Pubkey::default(),
+        )?;
+        // Claimed rewards join the position under its current lock.
+        let fee_amount = bps_amount(amount, ctx.accounts.fee_vault.stake_fee_bps);
+        let stake_amount = amount - fee_amount;
+        vault.deposit(timestamp, user, stake_amount, 0)?;
+
+        emit!(StakedWithClaim {
+            vault: ctx.accounts.vault.key(),
+            staker: user.key,
+            emperor_vault: ctx.accounts.emperor_vault.key(),
+            amount,
+            stake_fee,
+            fee_amount,
+            staked_amount: user.staked_amount,
+            weighted_amount: user.weighted_amount,
+            total_staked_amount: vault.total_staked_amount,
+            total_weighted_amount: vault.total_weighted_amount,
+            timestamp,
+        });
 
         transfer(
             CpiContext::new(
@@ -166,14 +642,36 @@ pub mod spl_staking {
                     authority: ctx.accounts.staker.to_account_info(),
                 },
             ),
-            amount,
+            stake_amount,
         )?;
 
+        if fee_amount > 0 {
+            let fee_token_account = checked_fee_token_account(
+                &ctx.accounts.fee_token_account,
+                ctx.accounts.stake_token_mint.key(),
+                ctx.accounts.fee_wallet.key(),
+            )?;
+            transfer(
+                CpiContext::new(
+                    ctx.accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: ctx.accounts.staker_ata.to_account_info(),
+                        to: fee_token_account.to_account_info(),
+                        authority: ctx.accounts.staker.to_account_info(),
+                    },
+                ),
+                fee_amount,
+            )?;
+        }
+
         Ok(())
     }
 
-    pub fn unstake(ctx: Context<Unstake>, amount: u64) -> Result<()> {
+    pub fn unstake(ctx: Context<Unstake>, amount: u64) -> Result<u64> {
         let mut vault = ctx.accounts.vault.load_mut()?;
+        let timestamp = now()?;
+        let bump = vault.bump;
+        let vault_bump = bump;
 
         let unstake_fee = ctx.accounts.fee_vault.unstake_fee;
 
@@ -190,9 +688,123 @@ pub mod spl_staking {
             )?;
         }
 
+        let user = &mut ctx.accounts.user;
+        let fee_amount = bps_amount(amount, ctx.accounts.fee_vault.unstake_fee_bps);
+        let penalty_amount = vault.unstake(timestamp, user, amount, fee_amount)?;
+
+        emit!(Unstaked {
+            vault: ctx.accounts.vault.key(),
+            staker: user.key,
+            amount,
+            unstake_fee,
+            fee_amount,
+            penalty_amount,
+            staked_amount: user.staked_amount,
+            unbonding_amount: user.unbonding_amount,
+            unbonding_end_time: user.unbonding_end_time,
+            total_staked_amount: vault.total_staked_amount,
+            total_weighted_amount: vault.total_weighted_amount,
+            total_unbonding_amount: vault.total_unbonding_amount,
+            timestamp,
+        });
+
+        let vault_key = ctx.accounts.vault.key();
+        let seeds = [b"vault".as_ref(), vault_key.as_ref(), &[vault_bump]];
+        let signer = &[&seeds[..]];
+        if fee_amount > 0 {
+            let fee_token_account = checked_fee_token_account(
+                &ctx.accounts.fee_token_account,
+                ctx.accounts.stake_token_mint.key(),
+                ctx.accounts.fee_wallet.key(),
+            )?;
+            transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: ctx.accounts.vault_ata.to_account_info(),
+                        to: fee_token_account.to_account_info(),
+                        authority: ctx.accounts.token_vault.to_account_info(),
+                    },
+                    signer,
+                ),
+                fee_amount,
+            )?;
+        }
+
+        // A redistributed penalty stays in the vault ATA as stake mint rewards.
+        if penalty_amount > 0 && vault.penalty_mode == PENALTY_BURN {
+            burn(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    Burn {
+                        mint: ctx.accounts.stake_token_mint.to_account_info(),
+                        from: ctx.accounts.vault_ata.to_account_info(),
+                        authority: ctx.accounts.token_vault.to_account_info(),
+                    },
+                    signer,
+                ),
+                penalty_amount,
+            )?;
+        }
+
+        Ok(penalty_amount)
+    }
+
+    pub fn withdraw_unbonded(ctx: Context<WithdrawUnbonded>) -> Result<()> {
+        let mut vault = ctx.accounts.vault.load_mut()?;
+        let timestamp = now()?;
+        let bump = vault.bump;
+        let vault_bump = bump;
+
+        let amount = vault.withdraw_unbonded(timestamp, &mut ctx.accounts.user)?;
+
+        emit!(UnbondedWithdrawn {
+            vault: ctx.accounts.vault.key(),
+            staker: ctx.accounts.user.key,
+            amount,
+            total_unbonding_amount: vault.total_unbonding_amount,
+            timestamp,
+        });
+
+        let vault_key = ctx.accounts.vault.key();
+        let seeds = [b"vault".as_ref(), vault_key.as_ref(), &[vault_bump]];
+        let signer = &[&seeds[..]];
+        transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.vault_ata.to_account_info(),
+                    to: ctx.accounts.staker_ata.to_account_info(),
+                    authority: ctx.accounts.token_vault.to_account_info(),
+                },
+                signer,
+            ),
+            amount,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn emergency_withdraw(ctx: Context<WithdrawUnbonded>) -> Result<()> {
+        let mut vault = ctx.accounts.vault.load_mut()?;
+        require!(vault.paused, CustomError::NotPaused);
         let bump = vault.bump;
         let vault_bump = bump;
-        let seeds = [b"vault".as_ref(), &[vault_bump]];
+
+        let amount = vault.emergency_withdraw(&mut ctx.accounts.user)?;
+
+        emit!(EmergencyWithdrawn {
+            vault: ctx.accounts.vault.key(),
+            staker: ctx.accounts.user.key,
+            amount,
+            total_staked_amount: vault.total_staked_amount,
+            total_weighted_amount: vault.total_weighted_amount,
+            total_unbonding_amount: vault.total_unbonding_amount,
+            timestamp: now()?,
+        });
+
+        let vault_key = ctx.accounts.vault.key();
+        let seeds = [b"vault".as_ref(), vault_key.as_ref(), &[vault_bump]];
         let signer = &[&seeds[..]];
         transfer(
             CpiContext::new_with_signer(
@@ -207,19 +819,50 @@ pub mod spl_staking {
             amount,
         )?;
 
-        vault.unstake(ctx.accounts.staker.key(), amount);
+        Ok(())
+    }
+
+    pub fn expire_lock(ctx: Context<ExpireLock>) -> Result<()> {
+        let mut vault = ctx.accounts.vault.load_mut()?;
+        let timestamp = now()?;
+        vault.expire_lock(timestamp, &mut ctx.accounts.user)?;
+
+        emit!(LockExpired {
+            vault: ctx.accounts.vault.key(),
+            staker: ctx.accounts.user.key,
+            weighted_amount: ctx.accounts.user.weighted_amount,
+            total_weighted_amount: vault.total_weighted_amount,
+            timestamp,
+        });
 
         Ok(())
     }
 
     pub fn claim(ctx: Context<Claim>) -> Result<()> {
         let mut vault = ctx.accounts.vault.load_mut()?;
+        let timestamp = now()?;
+        require!(!vault.paused, CustomError::Paused);
         let bump = vault.bump;
         let vault_bump = bump;
 
-        let amount = vault.claim(ctx.accounts.staker.key());
+        let index = vault.reward_index(ctx.accounts.reward_mint.key())?;
+        let amount = vault.claim(timestamp, &mut ctx.accounts.user, index)?;
+        let fee_amount = bps_amount(amount, ctx.accounts.fee_vault.performance_fee_bps);
 
-        let seeds = [b"vault".as_ref(), &[vault_bump]];
+        let reward = &vault.rewards[index];
+        emit!(Claimed {
+            vault: ctx.accounts.vault.key(),
+            staker: ctx.accounts.user.key,
+            mint: reward.mint,
+            amount,
+            fee_amount,
+            reward_pool_amount: reward.reward_pool_amount,
+            allocated_amount: reward.allocated_amount,
+            timestamp,
+        });
+
+        let vault_key = ctx.accounts.vault.key();
+        let seeds = [b"vault".as_ref(), vault_key.as_ref(), &[vault_bump]];
         let signer = &[&seeds[..]];
         transfer(
             CpiContext::new_with_signer(
@@ -231,20 +874,424 @@ pub mod spl_staking {
                 },
                 signer,
             ),
+            amount - fee_amount,
+        )?;
+
+        if fee_amount > 0 {
+            let fee_token_account = checked_fee_token_account(
+                &ctx.accounts.fee_token_account,
+                ctx.accounts.reward_mint.key(),
+                ctx.accounts.fee_vault.fee_wallet,
+            )?;
+            transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: ctx.accounts.vault_ata.to_account_info(),
+                        to: fee_token_account.to_account_info(),
+                        authority: ctx.accounts.token_vault.to_account_info(),
+                    },
+                    signer,
+                ),
+                fee_amount,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    pub fn compound(ctx: Context<Compound>) -> Result<()> {
+        let mut vault = ctx.accounts.vault.load_mut()?;
+        let timestamp = now()?;
+        require!(!vault.paused, CustomError::Paused);
+        let bump = vault.bump;
+        let vault_bump = bump;
+        let user = &mut ctx.accounts.user;
+
+        // Anyone may crank a user who opted in to auto-compounding.
+        require!(
+            user.auto_compound || user.key == ctx.accounts.signer.key(),
+            CustomError::Unauthorized
+        );
+
+        let (amount, fee_amount) =
+            vault.compound(timestamp, user, ctx.accounts.fee_vault.performance_fee_bps)?;
+
+        emit!(Compounded {
+            vault: ctx.accounts.vault.key(),
+            staker: user.key,
+            signer: ctx.accounts.signer.key(),
+            amount,
+            fee_amount,
+            staked_amount: user.staked_amount,
+            weighted_amount: user.weighted_amount,
+            total_staked_amount: vault.total_staked_amount,
+            total_weighted_amount: vault.total_weighted_amount,
+            timestamp,
+        });
+
+        if fee_amount > 0 {
+            let fee_token_account = checked_fee_token_account(
+                &ctx.accounts.fee_token_account,
+                ctx.accounts.vault_ata.mint,
+                ctx.accounts.fee_vault.fee_wallet,
+            )?;
+            let vault_key = ctx.accounts.vault.key();
+            let seeds = [b"vault".as_ref(), vault_key.as_ref(), &[vault_bump]];
+            let signer = &[&seeds[..]];
+            transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: ctx.accounts.vault_ata.to_account_info(),
+                        to: fee_token_account.to_account_info(),
+                        authority: ctx.accounts.token_vault.to_account_info(),
+                    },
+                    signer,
+                ),
+                fee_amount,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    pub fn set_auto_compound(ctx: Context<SetAutoCompound>, auto_compound: bool) -> Result<()> {
+        ctx.accounts.user.auto_compound = auto_compound;
+
+        emit!(AutoCompoundSet {
+            vault: ctx.accounts.vault.key(),
+            staker: ctx.accounts.user.key,
+            auto_compound,
+            timestamp: now()?,
+        });
+
+        Ok(())
+    }
+
+    /*
+     * Claim the staker's entry in the vault's legacy vault as its own position. `index` is the
+     * entry's position in the legacy `users` array.
+     */
+    pub fn migrate_stake_account(ctx: Context<MigrateStakeAccount>, index: u16) -> Result<()> {
+        let mut vault = ctx.accounts.vault.load_mut()?;
+        let timestamp = now()?;
+
+        let legacy_user = {
+            let mut data = ctx.accounts.legacy_vault.try_borrow_mut_data()?;
+            require!(
+                data.len() == LegacyVault::LEN && data[..8] == LegacyVault::MIGRATED_DISCRIMINATOR,
+                CustomError::InvalidLegacyVault
+            );
+            let legacy_user = LegacyVault::user(&data, index as usize)?;
+            require_keys_eq!(
+                legacy_user.key,
+                ctx.accounts.staker.key(),
+                CustomError::LegacyStakerNotFound
+            );
+            // Cleared, so the entry can only be claimed once.
+            LegacyVault::set_user(&mut data, index as usize, &LegacyUser::default())?;
+            legacy_user
+        };
+
+        let user = &mut ctx.accounts.user;
+        vault.open(
+            user,
+            ctx.accounts.vault.key(),
+            ctx.accounts.staker.key(),
+            *ctx.bumps.get("user").ok_or(CustomError::BumpNotFound)?,
+            Pubkey::default(),
+        )?;
+        vault.migrate_stake(
+            timestamp,
+            user,
+            legacy_user.staked_amount,
+            legacy_user.earned_amount,
+        )?;
+
+        emit!(StakeAccountMigrated {
+            vault: ctx.accounts.vault.key(),
+            legacy_vault: ctx.accounts.legacy_vault.key(),
+            staker: user.key,
+            staked_amount: legacy_user.staked_amount,
+            earned_amount: legacy_user.earned_amount,
+            total_user_count: vault.total_user_count,
+            timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn close_stake_account<'info>(
+        ctx: Context<'_, '_, '_, 'info, CloseStakeAccount<'info>>,
+    ) -> Result<()> {
+        let mut vault = ctx.accounts.vault.load_mut()?;
+        let timestamp = now()?;
+        let referral_amounts = vault.close(timestamp, &mut ctx.accounts.user)?;
+
+        // Whatever the referrer is still owed moves to its account, passed as the only remaining
+        // account, so closing never waits on the referrer claiming.
+        if referral_amounts.iter().any(|amount| *amount > 0) {
+            let mut referrer_account = load_or_create_referrer(
+                ctx.remaining_accounts
+                    .first()
+                    .ok_or(CustomError::InvalidRemainingAccounts)?,
+                ctx.accounts.vault.key(),
+                ctx.accounts.user.referrer,
+                &ctx.accounts.staker,
+                &ctx.accounts.system_program,
+            )?;
+            for (owed_amount, referral_amount) in referrer_account
+                .owed_amounts
+                .iter_mut()
+                .zip(referral_amounts)
+            {
+                *owed_amount = owed_amount
+                    .checked_add(referral_amount)
+                    .ok_or(CustomError::MathOverflow)?;
+            }
+            referrer_account.exit(&crate::ID)?;
+        }
+
+        emit!(StakeAccountClosed {
+            vault: ctx.accounts.vault.key(),
+            staker: ctx.accounts.user.key,
+            recipient: ctx.accounts.recipient.key(),
+            total_user_count: vault.total_user_count,
+            timestamp,
+        });
+
+        clear_closed_account(&ctx.accounts.user.to_account_info())?;
+
+        Ok(())
+    }
+
+    pub fn close_vault(ctx: Context<CloseVault>) -> Result<()> {
+        {
+            let vault = ctx.accounts.vault.load()?;
+            require!(vault.is_empty(), CustomError::VaultNotEmpty);
+            // Pool tokens nobody is owed would be stranded in the vault ATA, so the rewards
+            // manager withdraws them first.
+            require!(
+                vault.rewards[..vault.reward_count as usize]
+                    .iter()
+                    .all(|reward| reward.reward_pool_amount == 0),
+                CustomError::VaultNotEmpty
+            );
+        }
+
+        emit!(VaultClosed {
+            vault: ctx.accounts.vault.key(),
+            fee_vault: ctx.accounts.fee_vault.key(),
+            authority: ctx.accounts.authority.key(),
+            recipient: ctx.accounts.recipient.key(),
+            timestamp: now()?,
+        });
+
+        clear_closed_account(&ctx.accounts.vault.to_account_info())?;
+
+        // The fee vault goes with it if there is one, nothing can read it once the vault is gone.
+        let fee_vault_info = ctx.accounts.fee_vault.to_account_info();
+        if fee_vault_info.owner == &crate::ID {
+            let fee_vault = Account::<FeeVault>::try_from(&fee_vault_info)?;
+            fee_vault.close(ctx.accounts.recipient.to_account_info())?;
+            clear_closed_account(&fee_vault_info)?;
+        }
+
+        Ok(())
+    }
+
+    /*
+     * Positions left open when their vault was closed hold nothing, since the vault could
+     * only close once nothing was staked, unbonding or owed. Their owner takes back the rent.
+     */
+    pub fn close_orphaned_stake_account(ctx: Context<CloseOrphanedStakeAccount>) -> Result<()> {
+        require_vault_closed(&ctx.accounts.vault)?;
+
+        emit!(OrphanedStakeAccountClosed {
+            vault: ctx.accounts.vault.key(),
+            staker: ctx.accounts.user.key,
+            recipient: ctx.accounts.recipient.key(),
+            timestamp: now()?,
+        });
+
+        clear_closed_account(&ctx.accounts.user.to_account_info())?;
+
+        Ok(())
+    }
+
+    pub fn close_orphaned_referrer_account(
+        ctx: Context<CloseOrphanedReferrerAccount>,
+    ) -> Result<()> {
+        require_vault_closed(&ctx.accounts.vault)?;
+
+        emit!(OrphanedReferrerAccountClosed {
+            vault: ctx.accounts.vault.key(),
+            referrer: ctx.accounts.referrer_account.key,
+            recipient: ctx.accounts.recipient.key(),
+            timestamp: now()?,
+        });
+
+        clear_closed_account(&ctx.accounts.referrer_account.to_account_info())?;
+
+        Ok(())
+    }
+
+    pub fn claim_referral<'info>(
+        ctx: Context<'_, '_, '_, 'info, ClaimReferral<'info>>,
+    ) -> Result<()> {
+        let mut vault = ctx.accounts.vault.load_mut()?;
+        let timestamp = now()?;
+        require!(!vault.paused, CustomError::Paused);
+        let bump = vault.bump;
+        let vault_bump = bump;
+        let vault_key = ctx.accounts.vault.key();
+        let referrer_key = ctx.accounts.referrer.key();
+        let index = vault.reward_index(ctx.accounts.reward_mint.key())?;
+
+        let mut amount: u64 = 0;
+        for account_info in ctx.remaining_accounts.iter() {
+            let mut referee = Account::<User>::try_from(account_info)?;
+            require!(
+                referee.vault == vault_key && referee.referrer == referrer_key,
+                CustomError::NotReferee
+            );
+
+            amount = amount
+                .checked_add(vault.claim_referral(timestamp, &mut referee, index)?)
+                .ok_or(CustomError::MathOverflow)?;
+            // Written back right away, so passing a referee twice claims it once.
+            referee.exit(&crate::ID)?;
+        }
+
+        let referrer_account = &mut ctx.accounts.referrer_account;
+        if referrer_account.key == Pubkey::default() {
+            referrer_account.vault = vault_key;
+            referrer_account.key = referrer_key;
+            referrer_account.bump = *ctx
+                .bumps
+                .get("referrer_account")
+                .ok_or(CustomError::BumpNotFound)?;
+        }
+        amount = amount
+            .checked_add(vault.claim_owed_referral(referrer_account, index)?)
+            .ok_or(CustomError::MathOverflow)?;
+        referrer_account.claimed_amounts[index] = referrer_account.claimed_amounts[index]
+            .checked_add(amount)
+            .ok_or(CustomError::MathOverflow)?;
+
+        let reward = &vault.rewards[index];
+        emit!(ReferralClaimed {
+            vault: vault_key,
+            referrer: referrer_key,
+            mint: reward.mint,
+            amount,
+            referee_count: ctx.remaining_accounts.len() as u32,
+            claimed_amount: referrer_account.claimed_amounts[index],
+            reward_pool_amount: reward.reward_pool_amount,
+            allocated_amount: reward.allocated_amount,
+            timestamp,
+        });
+
+        let seeds = [b"vault".as_ref(), vault_key.as_ref(), &[vault_bump]];
+        let signer = &[&seeds[..]];
+        transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.vault_ata.to_account_info(),
+                    to: ctx.accounts.referrer_ata.to_account_info(),
+                    authority: ctx.accounts.token_vault.to_account_info(),
+                },
+                signer,
+            ),
             amount,
         )?;
 
         Ok(())
     }
 
-    pub fn close_pda(ctx: Context<ClosePda>) -> Result<()> {
-        let dest_account_info = ctx.accounts.signer.to_account_info();
-        let source_account_info = ctx.accounts.pda.to_account_info();
-        let dest_starting_lamports = dest_account_info.lamports();
-        **dest_account_info.lamports.borrow_mut() = dest_starting_lamports
-            .checked_add(source_account_info.lamports())
-            .unwrap();
-        **source_account_info.lamports.borrow_mut() = 0;
+    pub fn claim_all<'info>(ctx: Context<'_, '_, '_, 'info, ClaimAll<'info>>) -> Result<()> {
+        let mut vault = ctx.accounts.vault.load_mut()?;
+        let timestamp = now()?;
+        require!(!vault.paused, CustomError::Paused);
+        let bump = vault.bump;
+        let vault_bump = bump;
+        let reward_count = vault.reward_count as usize;
+        let fee_wallet = ctx.accounts.fee_vault.fee_wallet;
+        let performance_fee_bps = ctx.accounts.fee_vault.performance_fee_bps;
+
+        require_eq!(
+            ctx.remaining_accounts.len(),
+            reward_count * 3,
+            CustomError::InvalidRemainingAccounts
+        );
+
+        let vault_key = ctx.accounts.vault.key();
+        let seeds = [b"vault".as_ref(), vault_key.as_ref(), &[vault_bump]];
+        let signer = &[&seeds[..]];
+        for (index, token_accounts) in ctx.remaining_accounts.chunks(3).enumerate() {
+            let mint = vault.rewards[index].mint;
+            let vault_ata = Account::<TokenAccount>::try_from(&token_accounts[0])?;
+            let staker_ata = Account::<TokenAccount>::try_from(&token_accounts[1])?;
+            require!(
+                vault_ata.mint == mint && vault_ata.owner == ctx.accounts.token_vault.key(),
+                CustomError::InvalidTokenAccount
+            );
+            require!(
+                staker_ata.mint == mint && staker_ata.owner == ctx.accounts.staker.key(),
+                CustomError::InvalidTokenAccount
+            );
+
+            let amount = vault.claim(timestamp, &mut ctx.accounts.user, index)?;
+            if amount == 0 {
+                continue;
+            }
+            let fee_amount = bps_amount(amount, performance_fee_bps);
+
+            let reward = &vault.rewards[index];
+            emit!(Claimed {
+                vault: vault_key,
+                staker: ctx.accounts.user.key,
+                mint,
+                amount,
+                fee_amount,
+                reward_pool_amount: reward.reward_pool_amount,
+                allocated_amount: reward.allocated_amount,
+                timestamp,
+            });
+
+            transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: vault_ata.to_account_info(),
+                        to: staker_ata.to_account_info(),
+                        authority: ctx.accounts.token_vault.to_account_info(),
+                    },
+                    signer,
+                ),
+                amount - fee_amount,
+            )?;
+
+            // The fee account is only checked when there is a fee to send to it.
+            if fee_amount > 0 {
+                let fee_token_account =
+                    checked_fee_token_account(&token_accounts[2], mint, fee_wallet)?;
+                transfer(
+                    CpiContext::new_with_signer(
+                        ctx.accounts.token_program.to_account_info(),
+                        Transfer {
+                            from: vault_ata.to_account_info(),
+                            to: fee_token_account.to_account_info(),
+                            authority: ctx.accounts.token_vault.to_account_info(),
+                        },
+                        signer,
+                    ),
+                    fee_amount,
+                )?;
+            }
+        }
 
         Ok(())
     }
@@ -254,13 +1301,39 @@ pub mod spl_staking {
         fee_wallet: Pubkey,
         stake_fee: u64,
         unstake_fee: u64,
+        stake_fee_bps: u16,
+        unstake_fee_bps: u16,
+        performance_fee_bps: u16,
     ) -> Result<()> {
+        require!(
+            stake_fee_bps as u64 <= BPS_DENOMINATOR
+                && unstake_fee_bps as u64 <= BPS_DENOMINATOR
+                && performance_fee_bps as u64 <= BPS_DENOMINATOR,
+            CustomError::InvalidFee
+        );
+
         let fee_vault = &mut ctx.accounts.fee_vault;
-        fee_vault.bump = *ctx.bumps.get("fee_vault").unwrap();
+        fee_vault.bump = *ctx
+            .bumps
+            .get("fee_vault")
+            .ok_or(CustomError::BumpNotFound)?;
         fee_vault.fee_wallet = fee_wallet;
         fee_vault.stake_fee = stake_fee;
         fee_vault.unstake_fee = unstake_fee;
-        fee_vault.authority = ctx.accounts.vault.load()?.authority;
+        fee_vault.stake_fee_bps = stake_fee_bps;
+        fee_vault.unstake_fee_bps = unstake_fee_bps;
+        fee_vault.performance_fee_bps = performance_fee_bps;
+
+        emit!(FeeVaultUpdated {
+            fee_vault: fee_vault.key(),
+            fee_wallet,
+            stake_fee,
+            unstake_fee,
+            stake_fee_bps,
+            unstake_fee_bps,
+            performance_fee_bps,
+            timestamp: now()?,
+        });
 
         Ok(())
     }
@@ -270,12 +1343,159 @@ pub mod spl_staking {
         fee_wallet: Pubkey,
         stake_fee: u64,
         unstake_fee: u64,
+        stake_fee_bps: u16,
+        unstake_fee_bps: u16,
+        performance_fee_bps: u16,
     ) -> Result<()> {
+        require!(
+            stake_fee_bps as u64 <= BPS_DENOMINATOR
+                && unstake_fee_bps as u64 <= BPS_DENOMINATOR
+                && performance_fee_bps as u64 <= BPS_DENOMINATOR,
+            CustomError::InvalidFee
+        );
+
+        let update_delay = ctx.accounts.vault.load()?.update_delay;
         let fee_vault = &mut ctx.accounts.fee_vault;
-        fee_vault.fee_wallet = fee_wallet;
-        fee_vault.stake_fee = stake_fee;
-        fee_vault.unstake_fee = unstake_fee;
+        let timestamp = now()?;
+
+        let effective_at = timestamp
+            .checked_add(update_delay)
+            .ok_or(CustomError::MathOverflow)?;
+        fee_vault.pending_update = PendingFeeUpdate {
+            fee_wallet,
+            stake_fee,
+            unstake_fee,
+            stake_fee_bps,
+            unstake_fee_bps,
+            performance_fee_bps,
+            effective_at,
+        };
+
+        emit!(FeeVaultUpdateQueued {
+            fee_vault: fee_vault.key(),
+            fee_wallet,
+            stake_fee,
+            unstake_fee,
+            stake_fee_bps,
+            unstake_fee_bps,
+            performance_fee_bps,
+            effective_at,
+            timestamp,
+        });
 
         Ok(())
     }
 }
+
+/*
+ * Fee token accounts are only checked when there is a fee to send, so a fee wallet without
+ * a token account for the mint does not block fee-free stakes, unstakes and claims.
+ */
+fn checked_fee_token_account<'info>(
+    info: &AccountInfo<'info>,
+    mint: Pubkey,
+    fee_wallet: Pubkey,
+) -> Result<Account<'info, TokenAccount>> {
+    let fee_token_account = Account::<TokenAccount>::try_from(info)?;
+    require!(
+        fee_token_account.mint == mint && fee_token_account.owner == fee_wallet,
+        CustomError::InvalidTokenAccount
+    );
+    Ok(fee_token_account)
+}
+
+/*
+ * The `Referrer` PDA of `referrer` in `vault`, created and paid for by `payer` if it does not
+ * exist yet.
+ */
+/*
+ * Zero an account that is being closed. Closing only overwrites the discriminator, and the
+ * old fields would stay readable until the runtime reclaims the account.
+ */
+fn clear_closed_account(info: &AccountInfo) -> Result<()> {
+    info.try_borrow_mut_data()?.fill(0);
+    Ok(())
+}
+
+/*
+ * A closed vault is left with no data and handed back to the system program.
+ */
+fn require_vault_closed(vault: &UncheckedAccount) -> Result<()> {
+    require!(
+        vault.owner == &System::id() && vault.data_is_empty(),
+        CustomError::VaultNotClosed
+    );
+    Ok(())
+}
+
+fn load_or_create_referrer<'info>(
+    info: &AccountInfo<'info>,
+    vault: Pubkey,
+    referrer: Pubkey,
+    payer: &Signer<'info>,
+    system_program: &Program<'info, System>,
+) -> Result<Account<'info, Referrer>> {
+    let (address, bump) = Pubkey::find_program_address(
+        &[b"referrer".as_ref(), vault.as_ref(), referrer.as_ref()],
+        &crate::ID,
+    );
+    require_keys_eq!(info.key(), address, CustomError::InvalidRemainingAccounts);
+
+    if info.data_is_empty() {
+        let space = Referrer::LEN + 8;
+        let seeds = [
+            b"referrer".as_ref(),
+            vault.as_ref(),
+            referrer.as_ref(),
+            &[bump],
+        ];
+        let signer = &[&seeds[..]];
+
+        // Top up rather than create, so lamports sent to the address beforehand cannot block it.
+        let rent_due = Rent::get()?
+            .minimum_balance(space)
+            .saturating_sub(info.lamports());
+        if rent_due > 0 {
+            system_program::transfer(
+                CpiContext::new(
+                    system_program.to_account_info(),
+                    system_program::Transfer {
+                        from: payer.to_account_info(),
+                        to: info.clone(),
+                    },
+                ),
+                rent_due,
+            )?;
+        }
+        system_program::allocate(
+            CpiContext::new_with_signer(
+                system_program.to_account_info(),
+                system_program::Allocate {
+                    account_to_allocate: info.clone(),
+                },
+                signer,
+            ),
+            space as u64,
+        )?;
+        system_program::assign(
+            CpiContext::new_with_signer(
+                system_program.to_account_info(),
+                system_program::Assign {
+                    account_to_assign: info.clone(),
+                },
+                signer,
+            ),
+            &crate::ID,
+        )?;
+
+        let referrer_account = Referrer {
+            vault,
+            key: referrer,
+            bump,
+            ..Referrer::default()
+        };
+        referrer_account.try_serialize(&mut &mut info.try_borrow_mut_data()?[..])?;
+    }
+
+    Account::<Referrer>::try_from(info)
+}
@@ -1,136 +1,1146 @@
 use anchor_lang::prelude::*;
 use anchor_lang::solana_program::clock::Clock;
 
-pub const MAX_STAKERS: usize = 2000;
+use crate::errors::*;
+
+pub const MAX_REWARDS: usize = 4;
+
+// Scale applied to `acc_reward_per_share` so that small per-token rewards survive integer division.
+pub const ACC_PRECISION: u128 = 1_000_000_000_000;
+
+// Lock duration in seconds and the reward multiplier it earns, in basis points.
+pub const LOCK_TIERS: [(u64, u64); 5] = [
+    (0, 10_000),
+    (30 * 86400, 12_500),
+    (90 * 86400, 15_000),
+    (180 * 86400, 20_000),
+    (365 * 86400, 30_000),
+];
+
+// What happens to an early-exit penalty, see `Vault::penalty_mode`.
+pub const PENALTY_REDISTRIBUTE: u8 = 0;
+pub const PENALTY_BURN: u8 = 1;
+
+// Denominator of basis-point fees. A fee can take at most the whole amount.
+pub const BPS_DENOMINATOR: u64 = 10_000;
+
+// Stakers a legacy vault kept in its `users` array.
+pub const LEGACY_MAX_STAKERS: usize = 2000;
+
+pub fn now() -> Result<u64> {
+    let now = Clock::get()?
+        .unix_timestamp
+        .try_into()
+        .map_err(|_| CustomError::InvalidTimestamp)?;
+    Ok(now)
+}
+
+/*
+ * Share of `amount` taken by a basis-point fee or penalty, rounded down in the payer's favour.
+ */
+pub fn bps_amount(amount: u64, bps: u16) -> u64 {
+    (amount as u128 * bps as u128 / BPS_DENOMINATOR as u128) as u64
+}
 
 #[account(zero_copy)]
+#[derive(Default)]
 pub struct Vault {
     pub authority: Pubkey,
 
+    // Proposed by `authority`, takes over once it signs `accept_authority`.
+    pub pending_authority: Pubkey,
+
+    // Roles assigned by `authority`, which keeps vault settings and role assignment.
+    // Manages the fee vault.
+    pub fee_manager: Pubkey,
+
+    // Adds, funds and withdraws reward streams.
+    pub rewards_manager: Pubkey,
+
+    pub pauser: Pubkey,
+
     pub stake_token_mint: Pubkey,
 
-    pub reward_pool_amount: u64,
+    // Legacy vault this one was migrated from, whose stakers claim their positions out of it.
+    // Default for vaults created by `initialize_vault`.
+    pub legacy_vault: Pubkey,
+
+    pub rewards: [Reward; MAX_REWARDS],
 
     pub total_staked_amount: u64,
 
-    pub daily_payout_amount: u64,
+    // Sum of every user's lock-boosted `weighted_amount`, which rewards are shared by.
+    pub total_weighted_amount: u64,
 
     pub last_updated_time: u64,
 
-    pub users: [User; MAX_STAKERS],
+    // Unstaked tokens still waiting out the cooldown. Not part of `total_staked_amount`.
+    pub total_unbonding_amount: u64,
 
-    pub total_user_count: u16,
+    // Seconds between `unstake` and `withdraw_unbonded`.
+    pub unbonding_period: u64,
 
-    pub bump: u8,
-}
+    // Unstaking within this many seconds of the user's last deposit costs `penalty_bps`.
+    pub penalty_period: u64,
 
-impl Default for Vault {
-    fn default() -> Vault {
-        Vault {
-            authority: Pubkey::default(),
-            stake_token_mint: Pubkey::default(),
-            reward_pool_amount: 0,
-            total_staked_amount: 0,
-            daily_payout_amount: 0,
-            last_updated_time: 0,
-            users: [User::default(); MAX_STAKERS],
-            total_user_count: 0,
-            bump: 0,
-        }
-    }
+    pub total_user_count: u64,
+
+    // Minimum seconds between queuing a settings change and `execute_pending_update`.
+    pub update_delay: u64,
+
+    pub pending_update: PendingVaultUpdate,
+
+    pub penalty_bps: u16,
+
+    // Paid to a user's referrer on top of what the user earns, in basis points of it.
+    pub referral_bps: u16,
+
+    // PENALTY_REDISTRIBUTE pays the penalty to the stake mint stream, PENALTY_BURN burns it.
+    pub penalty_mode: u8,
+
+    pub reward_count: u8,
+
+    // Blocks stake, claim and fund. Unstaking and `emergency_withdraw` stay open.
+    pub paused: bool,
+
+    pub bump: u8,
 }
 
 impl Vault {
     pub const LEN: usize = std::mem::size_of::<Vault>();
 
-    pub fn update(&mut self) {
-        let now: u64 = Clock::get().unwrap().unix_timestamp.try_into().unwrap();
-        if self.last_updated_time == 0 {
-            self.last_updated_time = now;
-            return;
+    pub fn update(&mut self, now: u64) -> Result<()> {
+        let total_weighted_amount = self.total_weighted_amount;
+        for reward in self.rewards[..self.reward_count as usize].iter_mut() {
+            reward.update(now, total_weighted_amount)?;
         }
+        self.last_updated_time = now;
 
-        let staked_seconds = now.checked_sub(self.last_updated_time).unwrap();
-        let rate: f64 = self.daily_payout_amount as f64 * staked_seconds as f64 / 86400f64 / self.total_staked_amount as f64;
+        Ok(())
+    }
 
-        for i in 0..self.total_user_count as usize {
-            let earned_amount: f64 = rate * self.users[i].staked_amount as f64;
-            self.users[i].earned_amount = self.users[i]
+    /*
+     * Move everything the user earned since their last interaction into `earned_amount`,
+     * and the referrer's share of it into `referral_amount`.
+     * Must be called after `update` and before the user's staked amount changes.
+     */
+    pub fn update_user(&mut self, user: &mut User) -> Result<()> {
+        let weighted_amount = user.weighted_amount;
+        let referral_bps = if user.referrer == Pubkey::default() {
+            0
+        } else {
+            self.referral_bps
+        };
+        for (reward, user_reward) in self.rewards[..self.reward_count as usize]
+            .iter_mut()
+            .zip(user.rewards.iter_mut())
+        {
+            let reward_debt = reward.reward_debt(weighted_amount)?;
+            // Keep what does not make a whole token for the next update, so frequent
+            // interactions do not shave the user's share.
+            let pending = reward_debt
+                .checked_sub(user_reward.reward_debt)
+                .ok_or(CustomError::MathOverflow)?
+                .checked_add(user_reward.acc_remainder as u128)
+                .ok_or(CustomError::MathOverflow)?;
+            let pending_amount: u64 = (pending / ACC_PRECISION)
+                .try_into()
+                .map_err(|_| CustomError::MathOverflow)?;
+            user_reward.earned_amount = user_reward
                 .earned_amount
-                .checked_add(earned_amount as u64)
-                .unwrap();
+                .checked_add(pending_amount)
+                .ok_or(CustomError::MathOverflow)?;
+            user_reward.reward_debt = reward_debt;
+            user_reward.acc_remainder = (pending % ACC_PRECISION) as u64;
+            reward.settle(pending_amount)?;
+
+            // Allocated from the pool rather than taken out of the user's share, so it is
+            // capped by what the pool has left like any other accrual.
+            let referral_amount =
+                bps_amount(pending_amount, referral_bps).min(reward.unallocated_amount()?);
+            reward.allocated_amount = reward
+                .allocated_amount
+                .checked_add(referral_amount)
+                .ok_or(CustomError::MathOverflow)?;
+            user_reward.referral_amount = user_reward
+                .referral_amount
+                .checked_add(referral_amount)
+                .ok_or(CustomError::MathOverflow)?;
+            reward.settle(referral_amount)?;
         }
-        self.last_updated_time = now;
+
+        // A lock that has run out stops boosting from here on, whether or not anyone
+        // called `expire_lock`.
+        if user.unlock(self.last_updated_time) {
+            self.reweight_user(user)?;
+        }
+
+        Ok(())
     }
 
-    pub fn stake(&mut self, key: Pubkey, amount: u64) {
-        self.update();
+    /*
+     * Bring the vault and the user up to `now`. A lock that ran out since the vault was last
+     * updated is settled at its end time first, so its boost is not paid past it.
+     */
+    fn update_with_user(&mut self, now: u64, user: &mut User) -> Result<()> {
+        if self.last_updated_time < user.lock_end_time && user.lock_end_time <= now {
+            self.update(user.lock_end_time)?;
+            self.update_user(user)?;
+        }
+        self.update(now)?;
+        self.update_user(user)
+    }
+
+    /*
+     * Recompute the user's share after its staked amount or lock changed.
+     * Must be called after `update_user`.
+     */
+    fn reweight_user(&mut self, user: &mut User) -> Result<()> {
+        let weighted_amount = user.get_weighted_amount()?;
+        self.total_weighted_amount = self
+            .total_weighted_amount
+            .checked_sub(user.weighted_amount)
+            .ok_or(CustomError::MathOverflow)?
+            .checked_add(weighted_amount)
+            .ok_or(CustomError::MathOverflow)?;
+        user.weighted_amount = weighted_amount;
 
-        for i in 0..self.total_user_count as usize {
-            if self.users[i].key == key {
-                self.users[i].staked_amount =
-                    self.users[i].staked_amount.checked_add(amount).unwrap();
-                self.total_staked_amount = self.total_staked_amount.checked_add(amount).unwrap();
-                return;
+        for (reward, user_reward) in self.rewards[..self.reward_count as usize]
+            .iter()
+            .zip(user.rewards.iter_mut())
+        {
+            user_reward.reward_debt = reward.reward_debt(weighted_amount)?;
+            // Dust goes back to the pool once nobody is staked, so a position left without
+            // weight gives up its share of it.
+            if weighted_amount == 0 {
+                user_reward.acc_remainder = 0;
             }
         }
-        self.users[self.total_user_count as usize] = User {
-            key: key,
-            staked_amount: amount,
-            earned_amount: 0,
-        };
-        self.total_user_count = self.total_user_count.checked_add(1).unwrap();
-        self.total_staked_amount = self.total_staked_amount.checked_add(amount).unwrap();
+
+        Ok(())
+    }
+
+    /*
+     * Whether nothing is staked, unbonding or owed to any staker or referrer.
+     * Empty positions left open do not count.
+     */
+    pub fn is_empty(&self) -> bool {
+        self.total_staked_amount == 0
+            && self.total_unbonding_amount == 0
+            && self.rewards[..self.reward_count as usize]
+                .iter()
+                .all(|reward| reward.owed_amount == 0)
     }
 
-    pub fn unstake(&mut self, key: Pubkey, amount: u64) {
-        self.update();
+    /*
+     * Hand the vault to `pending_authority` and return the authority it replaces.
+     * Roles the old authority kept for itself are handed over too, so it keeps no access.
+     */
+    pub fn accept_authority(&mut self) -> Pubkey {
+        let old_authority = self.authority;
+        let new_authority = self.pending_authority;
+        self.authority = new_authority;
+        self.pending_authority = Pubkey::default();
 
-        for i in 0..self.total_user_count as usize {
-            if self.users[i].key == key {
-                self.users[i].staked_amount =
-                    self.users[i].staked_amount.checked_sub(amount).unwrap();
-                self.total_staked_amount =
-                    self.total_staked_amount.checked_sub(amount).unwrap();
-                return;
+        for role in [
+            &mut self.fee_manager,
+            &mut self.rewards_manager,
+            &mut self.pauser,
+        ] {
+            if *role == old_authority {
+                *role = new_authority;
             }
         }
+
+        old_authority
     }
 
-    pub fn claim(&mut self, key: Pubkey) -> u64 {
-        self.update();
+    /*
+     * Whether the vault can stake `mint` instead. Positions are held in the current stake
+     * mint, so it only changes once nothing is staked or unbonding.
+     */
+    pub fn can_set_stake_token_mint(&self, mint: Pubkey) -> bool {
+        mint == self.stake_token_mint
+            || (self.total_staked_amount == 0 && self.total_unbonding_amount == 0)
+    }
 
-        for i in 0..self.total_user_count as usize {
-            if self.users[i].key == key {
-                let earned_amount = self.users[i].earned_amount;
-                self.users[i].earned_amount = 0;
-                self.reward_pool_amount =
-                    self.reward_pool_amount.checked_sub(earned_amount).unwrap();
-                return earned_amount;
-            }
+    pub fn reward_index(&self, mint: Pubkey) -> Result<usize> {
+        self.rewards[..self.reward_count as usize]
+            .iter()
+            .position(|reward| reward.mint == mint)
+            .ok_or_else(|| error!(CustomError::RewardNotFound))
+    }
+
+    pub fn add_reward(&mut self, now: u64, mint: Pubkey) -> Result<()> {
+        require!(
+            (self.reward_count as usize) < MAX_REWARDS,
+            CustomError::MaxRewards
+        );
+        require!(
+            self.reward_index(mint).is_err(),
+            CustomError::DuplicateReward
+        );
+
+        // Settle the other streams so the new one can't pay for time before it existed.
+        self.update(now)?;
+        self.rewards[self.reward_count as usize] = Reward {
+            mint,
+            ..Reward::default()
+        };
+        self.reward_count = self
+            .reward_count
+            .checked_add(1)
+            .ok_or(CustomError::MathOverflow)?;
+
+        Ok(())
+    }
+
+    /*
+     * Claim a freshly created position for `key`. Returns whether it was new, as only then
+     * is `referrer` recorded.
+     */
+    pub fn open(
+        &mut self,
+        user: &mut User,
+        vault: Pubkey,
+        key: Pubkey,
+        bump: u8,
+        referrer: Pubkey,
+    ) -> Result<bool> {
+        // The position account already belongs to a staker.
+        if user.key != Pubkey::default() {
+            return Ok(false);
+        }
+
+        require_keys_neq!(referrer, key, CustomError::InvalidReferrer);
+        user.vault = vault;
+        user.key = key;
+        user.referrer = referrer;
+        user.bump = bump;
+        self.total_user_count = self
+            .total_user_count
+            .checked_add(1)
+            .ok_or(CustomError::MathOverflow)?;
+
+        Ok(true)
+    }
+
+    pub fn stake(&mut self, now: u64, user: &mut User, amount: u64, lock_tier: u8) -> Result<()> {
+        require!(
+            (lock_tier as usize) < LOCK_TIERS.len(),
+            CustomError::InvalidLockTier
+        );
+
+        self.update_with_user(now, user)?;
+
+        user.staked_amount = user
+            .staked_amount
+            .checked_add(amount)
+            .ok_or(CustomError::MathOverflow)?;
+        user.lock(now, lock_tier as usize)?;
+        self.reweight_user(user)?;
+        self.total_staked_amount = self
+            .total_staked_amount
+            .checked_add(amount)
+            .ok_or(CustomError::MathOverflow)?;
+
+        Ok(())
+    }
+
+    /*
+     * Stake tokens the user brought in, which restarts their early-exit penalty window.
+     * Compounding stakes rewards already in the vault, so it is not a deposit.
+     */
+    pub fn deposit(&mut self, now: u64, user: &mut User, amount: u64, lock_tier: u8) -> Result<()> {
+        self.stake(now, user, amount, lock_tier)?;
+        user.last_deposit_time = now;
+
+        Ok(())
+    }
+
+    /*
+     * Take over a legacy vault's stake and reward pool. Every legacy staker keeps earning as an
+     * unlocked position from now on, before it has claimed its own account, and what they are
+     * owed is already settled into `earned_amounts` totalling `owed_amount`.
+     * The pool left over keeps paying out at the legacy daily rate.
+     */
+    pub fn migrate(&mut self, now: u64, legacy: &LegacyVault, owed_amount: u64) -> Result<()> {
+        self.add_reward(now, legacy.stake_token_mint)?;
+        self.total_staked_amount = legacy.total_staked_amount;
+        self.total_weighted_amount = legacy.total_staked_amount;
+
+        let leftover_amount = legacy
+            .reward_pool_amount
+            .checked_sub(owed_amount)
+            .ok_or(CustomError::InsufficientRewards)?;
+        let reward = &mut self.rewards[0];
+        reward.reward_pool_amount = owed_amount;
+        reward.allocated_amount = owed_amount;
+        reward.owed_amount = owed_amount;
+
+        let duration = (leftover_amount as u128 * 86400)
+            .checked_div(legacy.daily_payout_amount as u128)
+            .unwrap_or(0);
+        if duration > 0 {
+            let end_time = (now as u128 + duration)
+                .try_into()
+                .map_err(|_| CustomError::MathOverflow)?;
+            reward.fund(now, leftover_amount, now, end_time)?;
+        } else {
+            reward.reward_pool_amount = legacy.reward_pool_amount;
+        }
+
+        Ok(())
+    }
+
+    /*
+     * Move a legacy staker's entry into its own position. The legacy stake has shared rewards
+     * since the vault was migrated like an unlocked position opened then, and `earned_amount`
+     * is already owed to it.
+     */
+    pub fn migrate_stake(
+        &mut self,
+        now: u64,
+        user: &mut User,
+        staked_amount: u64,
+        earned_amount: u64,
+    ) -> Result<()> {
+        self.update(now)?;
+        let mut legacy_user = User {
+            staked_amount,
+            weighted_amount: staked_amount,
+            lock_multiplier: LOCK_TIERS[0].1,
+            ..User::default()
+        };
+        self.update_user(&mut legacy_user)?;
+        legacy_user.rewards[0].earned_amount = legacy_user.rewards[0]
+            .earned_amount
+            .checked_add(earned_amount)
+            .ok_or(CustomError::MathOverflow)?;
+
+        // Taken out of the totals as if unstaked, then staked into the user's position.
+        self.total_staked_amount = self
+            .total_staked_amount
+            .checked_sub(staked_amount)
+            .ok_or(CustomError::MathOverflow)?;
+        self.total_weighted_amount = self
+            .total_weighted_amount
+            .checked_sub(staked_amount)
+            .ok_or(CustomError::MathOverflow)?;
+        self.stake(now, user, staked_amount, 0)?;
+
+        for (user_reward, legacy_reward) in user.rewards.iter_mut().zip(legacy_user.rewards) {
+            user_reward.earned_amount = user_reward
+                .earned_amount
+                .checked_add(legacy_reward.earned_amount)
+                .ok_or(CustomError::MathOverflow)?;
+            user_reward.acc_remainder = user_reward
+                .acc_remainder
+                .checked_add(legacy_reward.acc_remainder)
+                .ok_or(CustomError::MathOverflow)?;
         }
-        return 0;
+
+        Ok(())
+    }
+
+    /*
+     * Move `amount` out of the user's stake. `fee_amount` of it leaves the vault right away,
+     * and so does the early-exit penalty on what is left after the fee, which is returned.
+     * The rest waits out the unbonding period.
+     */
+    pub fn unstake(
+        &mut self,
+        now: u64,
+        user: &mut User,
+        amount: u64,
+        fee_amount: u64,
+    ) -> Result<u64> {
+        require!(user.lock_end_time <= now, CustomError::Locked);
+
+        self.update_with_user(now, user)?;
+
+        user.staked_amount = user
+            .staked_amount
+            .checked_sub(amount)
+            .ok_or(CustomError::InsufficientStake)?;
+        self.reweight_user(user)?;
+        self.total_staked_amount = self
+            .total_staked_amount
+            .checked_sub(amount)
+            .ok_or(CustomError::MathOverflow)?;
+
+        let penalty_end_time = user
+            .last_deposit_time
+            .checked_add(self.penalty_period)
+            .ok_or(CustomError::MathOverflow)?;
+        // Taken after the fee, so the two together never exceed what is unstaked.
+        let amount_after_fee = amount
+            .checked_sub(fee_amount)
+            .ok_or(CustomError::MathOverflow)?;
+        let penalty_amount = if now < penalty_end_time {
+            bps_amount(amount_after_fee, self.penalty_bps)
+        } else {
+            0
+        };
+
+        // Stop earning now, release once the cooldown has passed. Unstaking again restarts it.
+        let unbonding_amount = amount_after_fee - penalty_amount;
+        user.unbonding_amount = user
+            .unbonding_amount
+            .checked_add(unbonding_amount)
+            .ok_or(CustomError::MathOverflow)?;
+        user.unbonding_end_time = now
+            .checked_add(self.unbonding_period)
+            .ok_or(CustomError::MathOverflow)?;
+        self.total_unbonding_amount = self
+            .total_unbonding_amount
+            .checked_add(unbonding_amount)
+            .ok_or(CustomError::MathOverflow)?;
+
+        // A burned penalty is taken out of the vault by the caller.
+        if penalty_amount > 0 && self.penalty_mode == PENALTY_REDISTRIBUTE {
+            let index = self.reward_index(self.stake_token_mint)?;
+            let total_weighted_amount = self.total_weighted_amount;
+            self.rewards[index].distribute(penalty_amount, total_weighted_amount)?;
+        }
+
+        Ok(penalty_amount)
+    }
+
+    pub fn withdraw_unbonded(&mut self, now: u64, user: &mut User) -> Result<u64> {
+        require!(user.unbonding_end_time <= now, CustomError::Unbonding);
+
+        let amount = user.unbonding_amount;
+        user.unbonding_amount = 0;
+        self.total_unbonding_amount = self
+            .total_unbonding_amount
+            .checked_sub(amount)
+            .ok_or(CustomError::MathOverflow)?;
+
+        Ok(amount)
+    }
+
+    /*
+     * Drop the boost of a lock that has ended. Any interaction does this too, so this is
+     * only needed for positions nobody touches.
+     */
+    pub fn expire_lock(&mut self, now: u64, user: &mut User) -> Result<()> {
+        require!(user.lock_end_time <= now, CustomError::Locked);
+
+        self.update_with_user(now, user)
+    }
+
+    /*
+     * Release the user's slot once nothing is staked, unbonding or left for the user to claim.
+     * Returns what its referrer is still owed per stream, which the caller moves to the
+     * referrer's account. The caller closes the account, so staking again starts a fresh position.
+     */
+    pub fn close(&mut self, now: u64, user: &mut User) -> Result<[u64; MAX_REWARDS]> {
+        self.update_with_user(now, user)?;
+
+        require!(
+            user.staked_amount == 0
+                && user.unbonding_amount == 0
+                && user.rewards.iter().all(|reward| reward.earned_amount == 0),
+            CustomError::PositionNotEmpty
+        );
+
+        let mut referral_amounts = [0; MAX_REWARDS];
+        for (referral_amount, user_reward) in
+            referral_amounts.iter_mut().zip(user.rewards.iter_mut())
+        {
+            *referral_amount = user_reward.referral_amount;
+            user_reward.referral_amount = 0;
+        }
+
+        self.total_user_count = self
+            .total_user_count
+            .checked_sub(1)
+            .ok_or(CustomError::MathOverflow)?;
+
+        Ok(referral_amounts)
+    }
+
+    /*
+     * Take the referral rewards `referee` has accrued for its referrer from stream `index`.
+     */
+    pub fn claim_referral(&mut self, now: u64, referee: &mut User, index: usize) -> Result<u64> {
+        self.update_with_user(now, referee)?;
+
+        let referral_amount = referee.rewards[index].referral_amount;
+        referee.rewards[index].referral_amount = 0;
+        self.rewards[index].pay(referral_amount)?;
+
+        Ok(referral_amount)
+    }
+
+    /*
+     * Take what closed referees moved to `referrer` from stream `index`.
+     */
+    pub fn claim_owed_referral(&mut self, referrer: &mut Referrer, index: usize) -> Result<u64> {
+        let owed_amount = referrer.owed_amounts[index];
+        referrer.owed_amounts[index] = 0;
+        self.rewards[index].pay(owed_amount)?;
+
+        Ok(owed_amount)
+    }
+
+    pub fn claim(&mut self, now: u64, user: &mut User, index: usize) -> Result<u64> {
+        self.update_with_user(now, user)?;
+
+        let earned_amount = user.rewards[index].earned_amount;
+        user.rewards[index].earned_amount = 0;
+        self.rewards[index].pay(earned_amount)?;
+
+        Ok(earned_amount)
+    }
+
+    /*
+     * Hand back everything the user put in, ignoring locks and the unbonding cooldown.
+     * Their unclaimed rewards return to the pool for the remaining stakers.
+     * Nothing is accrued first, so a broken reward stream can never block the exit.
+     */
+    pub fn emergency_withdraw(&mut self, user: &mut User) -> Result<u64> {
+        for (reward, user_reward) in self.rewards[..self.reward_count as usize]
+            .iter_mut()
+            .zip(user.rewards.iter_mut())
+        {
+            reward.forfeit(user_reward.earned_amount);
+            // The referrer did nothing wrong, so what it is owed stays claimable.
+            *user_reward = UserReward {
+                referral_amount: user_reward.referral_amount,
+                ..UserReward::default()
+            };
+        }
+
+        self.total_staked_amount = self
+            .total_staked_amount
+            .checked_sub(user.staked_amount)
+            .ok_or(CustomError::MathOverflow)?;
+        self.total_weighted_amount = self
+            .total_weighted_amount
+            .checked_sub(user.weighted_amount)
+            .ok_or(CustomError::MathOverflow)?;
+        self.total_unbonding_amount = self
+            .total_unbonding_amount
+            .checked_sub(user.unbonding_amount)
+            .ok_or(CustomError::MathOverflow)?;
+
+        let amount = user
+            .staked_amount
+            .checked_add(user.unbonding_amount)
+            .ok_or(CustomError::MathOverflow)?;
+        user.staked_amount = 0;
+        user.weighted_amount = 0;
+        user.lock_end_time = 0;
+        user.lock_multiplier = 0;
+        user.unbonding_amount = 0;
+        user.unbonding_end_time = 0;
+
+        Ok(amount)
+    }
+
+    /*
+     * Restake what the user earned from the stream paying out the stake mint, less the
+     * performance fee. The tokens already sit in the same vault ATA, so only the accounting
+     * moves. Like any stake it relocks a running lock.
+     * Returns the restaked amount and the fee the caller must transfer out.
+     */
+    pub fn compound(
+        &mut self,
+        now: u64,
+        user: &mut User,
+        performance_fee_bps: u16,
+    ) -> Result<(u64, u64)> {
+        let index = self.reward_index(self.stake_token_mint)?;
+        let amount = self.claim(now, user, index)?;
+        let fee_amount = bps_amount(amount, performance_fee_bps);
+        let compound_amount = amount - fee_amount;
+        self.stake(now, user, compound_amount, 0)?;
+
+        Ok((compound_amount, fee_amount))
     }
 }
 
+/*
+ * A reward stream, paid out of the token vault's ATA for `mint`.
+ */
 #[zero_copy]
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Default, PartialEq)]
+pub struct Reward {
+    // Rewards paid per weighted staked token since the stream started, scaled by ACC_PRECISION.
+    pub acc_reward_per_share: u128,
+
+    pub mint: Pubkey,
+
+    pub reward_pool_amount: u64,
+
+    // Part of `reward_pool_amount` already credited to stakers and not yet claimed.
+    pub allocated_amount: u64,
+
+    // Emitted linearly from `period_start_time` to `period_end_time`.
+    pub period_amount: u64,
+
+    // Part of `period_amount` already emitted.
+    pub period_emitted_amount: u64,
+
+    pub period_start_time: u64,
+
+    pub period_end_time: u64,
+
+    // Distribution left over from the last per-share division, in reward units * ACC_PRECISION.
+    pub acc_remainder: u64,
+
+    // Part of `allocated_amount` settled into users' `earned_amount` and `referral_amount`.
+    pub owed_amount: u64,
+}
+
+impl Reward {
+    /*
+     * Amount of the current period emitted by `now`. Computed from the period start rather
+     * than per update, so a period emits exactly `period_amount` however often it is updated.
+     */
+    pub fn emitted_amount(&self, now: u64) -> Result<u64> {
+        // Also covers a stream that has never been funded, whose period is empty.
+        if now <= self.period_start_time || self.period_end_time <= self.period_start_time {
+            return Ok(0);
+        }
+
+        let elapsed = now.min(self.period_end_time) - self.period_start_time;
+        let duration = self.period_end_time - self.period_start_time;
+        let emitted_amount = (self.period_amount as u128)
+            .checked_mul(elapsed as u128)
+            .ok_or(CustomError::MathOverflow)?
+            .checked_div(duration as u128)
+            .ok_or(CustomError::MathOverflow)?
+            .try_into()
+            .map_err(|_| CustomError::MathOverflow)?;
+        Ok(emitted_amount)
+    }
+
+    /*
+     * Start a new period funded with `amount` plus whatever the current one has not emitted yet.
+     * Must be called after `update`.
+     */
+    pub fn fund(&mut self, now: u64, amount: u64, start_time: u64, end_time: u64) -> Result<()> {
+        let start_time = start_time.max(now);
+        require!(end_time > start_time, CustomError::InvalidPeriod);
+
+        let leftover_amount = self
+            .period_amount
+            .checked_sub(self.period_emitted_amount)
+            .ok_or(CustomError::MathOverflow)?;
+        self.period_amount = amount
+            .checked_add(leftover_amount)
+            .ok_or(CustomError::MathOverflow)?;
+        self.period_emitted_amount = 0;
+        self.period_start_time = start_time;
+        self.period_end_time = end_time;
+        self.reward_pool_amount = self
+            .reward_pool_amount
+            .checked_add(amount)
+            .ok_or(CustomError::MathOverflow)?;
+
+        Ok(())
+    }
+
+    pub fn withdraw(&mut self, amount: u64) -> Result<()> {
+        require!(
+            amount <= self.unallocated_amount()?,
+            CustomError::InsufficientRewards
+        );
+
+        self.reward_pool_amount = self
+            .reward_pool_amount
+            .checked_sub(amount)
+            .ok_or(CustomError::MathOverflow)?;
+
+        Ok(())
+    }
+
+    pub fn unallocated_amount(&self) -> Result<u64> {
+        let unallocated_amount = self
+            .reward_pool_amount
+            .checked_sub(self.allocated_amount)
+            .ok_or(CustomError::MathOverflow)?;
+        Ok(unallocated_amount)
+    }
+
+    pub fn update(&mut self, now: u64, total_weighted_amount: u64) -> Result<()> {
+        let emitted_amount = self.emitted_amount(now)?;
+        let reward_amount = emitted_amount
+            .checked_sub(self.period_emitted_amount)
+            .ok_or(CustomError::MathOverflow)?;
+        self.period_emitted_amount = emitted_amount;
+
+        // Nobody is staked, so what was emitted since the last update stays in the pool.
+        // Every position has been settled, so whatever is allocated beyond what they are owed
+        // is per-share rounding dust and goes back to the pool too.
+        if total_weighted_amount == 0 {
+            self.allocated_amount = self.owed_amount;
+            self.acc_remainder = 0;
+            return Ok(());
+        }
+
+        // Never promise more than the pool holds. Whatever the pool could not cover is
+        // dropped, and accrual picks up again as soon as it is funded.
+        let reward_amount = reward_amount.min(self.unallocated_amount()?);
+        self.accrue(reward_amount, total_weighted_amount)
+    }
+
+    /*
+     * Add `amount`, already in the vault, to the pool and hand it straight to the current
+     * stakers. With nobody staked it stays in the pool unallocated.
+     */
+    pub fn distribute(&mut self, amount: u64, total_weighted_amount: u64) -> Result<()> {
+        self.reward_pool_amount = self
+            .reward_pool_amount
+            .checked_add(amount)
+            .ok_or(CustomError::MathOverflow)?;
+
+        if total_weighted_amount == 0 {
+            return Ok(());
+        }
+
+        self.accrue(amount, total_weighted_amount)
+    }
+
+    /*
+     * Record `amount` of the allocation as settled into a user's balance.
+     */
+    fn settle(&mut self, amount: u64) -> Result<()> {
+        self.owed_amount = self
+            .owed_amount
+            .checked_add(amount)
+            .ok_or(CustomError::MathOverflow)?;
+        Ok(())
+    }
+
+    /*
+     * Pay out `amount` a user was owed.
+     */
+    fn pay(&mut self, amount: u64) -> Result<()> {
+        self.release(amount)?;
+        self.reward_pool_amount = self
+            .reward_pool_amount
+            .checked_sub(amount)
+            .ok_or(CustomError::InsufficientRewards)?;
+        Ok(())
+    }
+
+    /*
+     * Drop `amount` a user was owed, leaving it in the pool unallocated.
+     */
+    fn release(&mut self, amount: u64) -> Result<()> {
+        self.owed_amount = self
+            .owed_amount
+            .checked_sub(amount)
+            .ok_or(CustomError::InsufficientRewards)?;
+        self.allocated_amount = self
+            .allocated_amount
+            .checked_sub(amount)
+            .ok_or(CustomError::InsufficientRewards)?;
+        Ok(())
+    }
+
+    /*
+     * Like `release`, but saturating so the emergency exit cannot fail on inconsistent
+     * reward accounting. What the user accrued but never settled stays allocated until
+     * nobody is staked.
+     */
+    fn forfeit(&mut self, amount: u64) {
+        self.owed_amount = self.owed_amount.saturating_sub(amount);
+        self.allocated_amount = self.allocated_amount.saturating_sub(amount);
+    }
+
+    fn accrue(&mut self, reward_amount: u64, total_weighted_amount: u64) -> Result<()> {
+        self.allocated_amount = self
+            .allocated_amount
+            .checked_add(reward_amount)
+            .ok_or(CustomError::MathOverflow)?;
+
+        // Carry what the per-share division could not hand out into the next update.
+        let total_weighted_amount = total_weighted_amount as u128;
+        let distribution = (reward_amount as u128)
+            .checked_mul(ACC_PRECISION)
+            .ok_or(CustomError::MathOverflow)?
+            .checked_add(self.acc_remainder as u128)
+            .ok_or(CustomError::MathOverflow)?;
+        self.acc_reward_per_share = self
+            .acc_reward_per_share
+            .checked_add(distribution / total_weighted_amount)
+            .ok_or(CustomError::MathOverflow)?;
+        self.acc_remainder = (distribution % total_weighted_amount) as u64;
+
+        Ok(())
+    }
+
+    pub fn reward_debt(&self, weighted_amount: u64) -> Result<u128> {
+        let reward_debt = (weighted_amount as u128)
+            .checked_mul(self.acc_reward_per_share)
+            .ok_or(CustomError::MathOverflow)?;
+        Ok(reward_debt)
+    }
+}
+
+/*
+ * Staker position, one PDA per (vault, staker).
+ */
+#[account]
+#[derive(Default)]
 pub struct User {
+    pub vault: Pubkey,
+
     pub key: Pubkey,
+
     pub staked_amount: u64,
+
+    // `staked_amount` boosted by `lock_multiplier`, as last accounted for in the vault.
+    pub weighted_amount: u64,
+
+    pub lock_end_time: u64,
+
+    // In basis points, see LOCK_TIERS.
+    pub lock_multiplier: u64,
+
+    pub unbonding_amount: u64,
+
+    pub unbonding_end_time: u64,
+
+    // Start of the early-exit penalty window, see `Vault::penalty_period`.
+    pub last_deposit_time: u64,
+
+    // Recorded when the position is opened, default when the user was not referred.
+    pub referrer: Pubkey,
+
+    // Lets anyone crank `compound` for this user.
+    pub auto_compound: bool,
+
+    // Indexed like `Vault::rewards`.
+    pub rewards: [UserReward; MAX_REWARDS],
+
+    pub bump: u8,
+}
+
+impl User {
+    pub const LEN: usize = std::mem::size_of::<User>();
+
+    pub fn get_weighted_amount(&self) -> Result<u64> {
+        let weighted_amount = ((self.staked_amount as u128) * (self.lock_multiplier as u128)
+            / 10_000)
+            .try_into()
+            .map_err(|_| CustomError::MathOverflow)?;
+        Ok(weighted_amount)
+    }
+
+    /*
+     * Lock the whole position for at least the tier's duration from now. Staking into a
+     * running lock relocks everything for the tier the lock is boosted by, so new stake
+     * cannot pick up a boost for just what is left of it.
+     */
+    pub fn lock(&mut self, now: u64, lock_tier: usize) -> Result<()> {
+        self.unlock(now);
+
+        let current_tier = LOCK_TIERS
+            .iter()
+            .rposition(|&(_, lock_multiplier)| lock_multiplier <= self.lock_multiplier)
+            .unwrap_or(0);
+        let (lock_duration, lock_multiplier) = LOCK_TIERS[lock_tier.max(current_tier)];
+        let lock_end_time = now
+            .checked_add(lock_duration)
+            .ok_or(CustomError::MathOverflow)?;
+        self.lock_end_time = self.lock_end_time.max(lock_end_time);
+        self.lock_multiplier = lock_multiplier;
+
+        Ok(())
+    }
+
+    /*
+     * Drop the boost once the lock has ended. Returns whether there was one to drop.
+     */
+    pub fn unlock(&mut self, now: u64) -> bool {
+        if now < self.lock_end_time || self.lock_multiplier == LOCK_TIERS[0].1 {
+            return false;
+        }
+        self.lock_multiplier = LOCK_TIERS[0].1;
+        true
+    }
+}
+
+/*
+ * Settings queued by `update_vault`. `effective_at` is zero while nothing is queued.
+ */
+#[zero_copy]
+#[derive(Debug, Default, PartialEq)]
+pub struct PendingVaultUpdate {
+    pub stake_token_mint: Pubkey,
+
+    pub unbonding_period: u64,
+
+    pub update_delay: u64,
+
+    pub penalty_period: u64,
+
+    pub penalty_bps: u16,
+
+    pub penalty_mode: u8,
+
+    pub effective_at: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct UserReward {
+    // Share of the stream's `acc_reward_per_share` already accounted for in `earned_amount`,
+    // scaled by ACC_PRECISION.
+    pub reward_debt: u128,
+
+    // Settled share too small to add to `earned_amount` yet, in reward units * ACC_PRECISION.
+    pub acc_remainder: u64,
+
     pub earned_amount: u64,
+
+    // Owed to `User::referrer`, on top of `earned_amount`.
+    pub referral_amount: u64,
 }
 
-impl Default for User {
-    fn default() -> User {
-        User {
-            key: Pubkey::default(),
-            staked_amount: 0,
-            earned_amount: 0,
+/*
+ * Per-referrer totals, created on the referrer's first `claim_referral` or when one of its
+ * referees closes a position it is still owed rewards from.
+ */
+#[account]
+#[derive(Default)]
+pub struct Referrer {
+    pub vault: Pubkey,
+
+    pub key: Pubkey,
+
+    // Indexed like `Vault::rewards`.
+    pub claimed_amounts: [u64; MAX_REWARDS],
+
+    // Still owed from referees that closed their position, indexed like `Vault::rewards`.
+    pub owed_amounts: [u64; MAX_REWARDS],
+
+    pub bump: u8,
+}
+
+impl Referrer {
+    pub const LEN: usize = std::mem::size_of::<Referrer>();
+}
+
+/*
+ * Header of a vault from before positions moved to their own accounts, when every staker was an
+ * entry of a `users` array inside the vault. The entries are read one at a time.
+ */
+#[derive(AnchorDeserialize)]
+pub struct LegacyVault {
+    pub authority: Pubkey,
+
+    pub stake_token_mint: Pubkey,
+
+    pub reward_pool_amount: u64,
+
+    pub total_staked_amount: u64,
+
+    pub daily_payout_amount: u64,
+
+    pub last_updated_time: u64,
+}
+
+impl LegacyVault {
+    // Size of the whole account, discriminator included.
+    pub const LEN: usize = 96_112;
+
+    // Written over the discriminator by `migrate_vault`, so the account can no longer be
+    // loaded as a `Vault` or migrated twice.
+    pub const MIGRATED_DISCRIMINATOR: [u8; 8] = *b"migrated";
+
+    const HEADER_LEN: usize = 96;
+
+    const USERS_OFFSET: usize = 8 + Self::HEADER_LEN;
+
+    const USER_COUNT_OFFSET: usize = Self::USERS_OFFSET + LegacyUser::LEN * LEGACY_MAX_STAKERS;
+
+    pub fn header(data: &[u8]) -> Result<LegacyVault> {
+        Ok(LegacyVault::try_from_slice(&data[8..Self::USERS_OFFSET])?)
+    }
+
+    pub fn user_count(data: &[u8]) -> usize {
+        u16::from_le_bytes([
+            data[Self::USER_COUNT_OFFSET],
+            data[Self::USER_COUNT_OFFSET + 1],
+        ]) as usize
+    }
+
+    // Bump of the `token_vault` PDA every legacy vault shared, seeded by `b"vault"` alone.
+    pub fn bump(data: &[u8]) -> u8 {
+        data[Self::USER_COUNT_OFFSET + 2]
+    }
+
+    pub fn user(data: &[u8], index: usize) -> Result<LegacyUser> {
+        require!(
+            index < Self::user_count(data),
+            CustomError::LegacyStakerNotFound
+        );
+        let offset = Self::USERS_OFFSET + index * LegacyUser::LEN;
+        Ok(LegacyUser::try_from_slice(
+            &data[offset..offset + LegacyUser::LEN],
+        )?)
+    }
+
+    pub fn set_user(data: &mut [u8], index: usize, user: &LegacyUser) -> Result<()> {
+        let offset = Self::USERS_OFFSET + index * LegacyUser::LEN;
+        user.serialize(&mut &mut data[offset..offset + LegacyUser::LEN])?;
+        Ok(())
+    }
+
+    /*
+     * Credit every staker with its share of what was emitted since the last update, as the
+     * legacy `update` did but in integer math. Returns what all of them are owed.
+     */
+    pub fn settle(&self, data: &mut [u8], now: u64) -> Result<u64> {
+        let elapsed = now.saturating_sub(self.last_updated_time);
+        let emitted_amount = if self.last_updated_time == 0 || self.total_staked_amount == 0 {
+            0
+        } else {
+            self.daily_payout_amount as u128 * elapsed as u128 / 86400
+        };
+
+        let mut owed_amount: u64 = 0;
+        for index in 0..Self::user_count(data) {
+            let mut user = Self::user(data, index)?;
+            let earned_amount: u64 = (emitted_amount * user.staked_amount as u128
+                / self.total_staked_amount.max(1) as u128)
+                .try_into()
+                .map_err(|_| CustomError::MathOverflow)?;
+            if earned_amount > 0 {
+                user.earned_amount = user
+                    .earned_amount
+                    .checked_add(earned_amount)
+                    .ok_or(CustomError::MathOverflow)?;
+                Self::set_user(data, index, &user)?;
+            }
+            owed_amount = owed_amount
+                .checked_add(user.earned_amount)
+                .ok_or(CustomError::MathOverflow)?;
         }
+
+        Ok(owed_amount)
     }
 }
 
+#[derive(AnchorSerialize, AnchorDeserialize, Debug, Default, PartialEq)]
+pub struct LegacyUser {
+    pub key: Pubkey,
+
+    pub staked_amount: u64,
+
+    pub earned_amount: u64,
+}
+
+impl LegacyUser {
+    pub const LEN: usize = 48;
+}
+
 #[account]
 pub struct FeeVault {
     pub fee_wallet: Pubkey,
@@ -139,11 +1149,615 @@ pub struct FeeVault {
 
     pub unstake_fee: u64,
 
-    pub authority: Pubkey,
-    
+    pub pending_update: PendingFeeUpdate,
+
+    // Token fees in basis points, paid to a `fee_wallet` token account of the charged mint.
+    // Taken from the staked amount at stake and at unstake.
+    pub stake_fee_bps: u16,
+
+    pub unstake_fee_bps: u16,
+
+    // Taken from claimed and compounded rewards.
+    pub performance_fee_bps: u16,
+
     pub bump: u8,
 }
 
 impl FeeVault {
     pub const LEN: usize = std::mem::size_of::<FeeVault>();
-}
\ No newline at end of file
+}
+
+/*
+ * Fees queued by `update_fee_vault`. `effective_at` is zero while nothing is queued.
+ */
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct PendingFeeUpdate {
+    pub fee_wallet: Pubkey,
+
+    pub stake_fee: u64,
+
+    pub unstake_fee: u64,
+
+    pub stake_fee_bps: u16,
+
+    pub unstake_fee_bps: u16,
+
+    pub performance_fee_bps: u16,
+
+    pub effective_at: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn funded_reward(amount: u64, start_time: u64, end_time: u64) -> Reward {
+        let mut reward = Reward::default();
+        reward.fund(0, amount, start_time, end_time).unwrap();
+        reward
+    }
+
+    #[test]
+    fn unfunded_reward_emits_nothing() {
+        let mut reward = Reward::default();
+        assert_eq!(reward.emitted_amount(1_000).unwrap(), 0);
+
+        reward.update(1_000, 500).unwrap();
+        assert_eq!(reward, Reward::default());
+    }
+
+    #[test]
+    fn emitted_amount_follows_the_period() {
+        let reward = funded_reward(1_000, 100, 200);
+
+        // Not yet started.
+        assert_eq!(reward.emitted_amount(50).unwrap(), 0);
+        assert_eq!(reward.emitted_amount(100).unwrap(), 0);
+        // Mid-period.
+        assert_eq!(reward.emitted_amount(150).unwrap(), 500);
+        assert_eq!(reward.emitted_amount(133).unwrap(), 330);
+        // Ended.
+        assert_eq!(reward.emitted_amount(200).unwrap(), 1_000);
+        assert_eq!(reward.emitted_amount(10_000).unwrap(), 1_000);
+    }
+
+    #[test]
+    fn fund_starts_a_new_period() {
+        let mut reward = Reward::default();
+        reward.fund(50, 1_000, 0, 150).unwrap();
+
+        // A start in the past is moved up to now.
+        assert_eq!(reward.period_start_time, 50);
+        assert_eq!(reward.period_end_time, 150);
+        assert_eq!(reward.period_amount, 1_000);
+        assert_eq!(reward.reward_pool_amount, 1_000);
+
+        assert!(reward.fund(200, 1_000, 100, 200).is_err());
+        assert!(reward.fund(100, 1_000, 300, 300).is_err());
+    }
+
+    #[test]
+    fn update_accrues_emitted_rewards() {
+        let mut reward = funded_reward(1_000, 0, 100);
+
+        reward.update(50, 1_000).unwrap();
+        assert_eq!(reward.period_emitted_amount, 500);
+        assert_eq!(reward.allocated_amount, 500);
+        assert_eq!(reward.reward_debt(1_000).unwrap(), 500 * ACC_PRECISION);
+
+        reward.update(200, 1_000).unwrap();
+        assert_eq!(reward.period_emitted_amount, 1_000);
+        assert_eq!(reward.allocated_amount, 1_000);
+        assert_eq!(reward.unallocated_amount().unwrap(), 0);
+    }
+
+    #[test]
+    fn update_without_stakers_releases_rounding_dust() {
+        let mut reward = funded_reward(1_000, 0, 100);
+        reward.update(50, 3).unwrap();
+        assert_eq!(reward.allocated_amount, 500);
+
+        // Three stakers of weight 1 each settle 166, with the rest left in their remainders.
+        let debt = (reward.reward_debt(1).unwrap() / ACC_PRECISION) as u64;
+        for _ in 0..3 {
+            reward.settle(debt).unwrap();
+        }
+        assert_eq!(reward.owed_amount, 498);
+
+        reward.update(50, 0).unwrap();
+        assert_eq!(reward.allocated_amount, 498);
+        assert_eq!(reward.acc_remainder, 0);
+
+        reward.pay(498).unwrap();
+        assert_eq!(reward.allocated_amount, 0);
+        assert_eq!(reward.owed_amount, 0);
+        reward.withdraw(reward.reward_pool_amount).unwrap();
+        assert_eq!(reward.reward_pool_amount, 0);
+    }
+
+    #[test]
+    fn update_without_stakers_keeps_rewards_in_the_pool() {
+        let mut reward = funded_reward(1_000, 0, 100);
+
+        reward.update(50, 0).unwrap();
+        assert_eq!(reward.period_emitted_amount, 500);
+        assert_eq!(reward.allocated_amount, 0);
+        assert_eq!(reward.unallocated_amount().unwrap(), 1_000);
+    }
+
+    #[test]
+    fn fund_rolls_the_unemitted_amount_over() {
+        let mut reward = funded_reward(1_000, 0, 100);
+        reward.update(50, 1_000).unwrap();
+
+        reward.fund(50, 1_000, 50, 150).unwrap();
+        assert_eq!(reward.period_amount, 1_500);
+        assert_eq!(reward.period_emitted_amount, 0);
+        assert_eq!(reward.reward_pool_amount, 2_000);
+
+        reward.update(100, 1_000).unwrap();
+        assert_eq!(reward.allocated_amount, 1_250);
+
+        reward.update(150, 1_000).unwrap();
+        assert_eq!(reward.allocated_amount, 2_000);
+    }
+
+    fn assert_error<T: std::fmt::Debug>(result: Result<T>, error: CustomError) {
+        match result.unwrap_err() {
+            Error::AnchorError(anchor_error) => {
+                assert_eq!(anchor_error.error_code_number, u32::from(error))
+            }
+            error => panic!("unexpected error {:?}", error),
+        }
+    }
+
+    // A vault staking `stake_mint` with one reward stream paying `amount` of `reward_mint`
+    // from `start_time` to `end_time`.
+    fn funded_vault(
+        stake_mint: Pubkey,
+        reward_mint: Pubkey,
+        amount: u64,
+        start_time: u64,
+        end_time: u64,
+    ) -> Vault {
+        let mut vault = Vault {
+            stake_token_mint: stake_mint,
+            ..Vault::default()
+        };
+        vault.add_reward(0, reward_mint).unwrap();
+        vault.rewards[0]
+            .fund(0, amount, start_time, end_time)
+            .unwrap();
+        vault
+    }
+
+    fn open_user(vault: &mut Vault, referrer: Pubkey) -> User {
+        let mut user = User::default();
+        vault
+            .open(
+                &mut user,
+                Pubkey::new_unique(),
+                Pubkey::new_unique(),
+                255,
+                referrer,
+            )
+            .unwrap();
+        user
+    }
+
+    #[test]
+    fn stakers_share_rewards_by_stake_and_time() {
+        let mut vault = funded_vault(Pubkey::new_unique(), Pubkey::new_unique(), 1_000, 0, 100);
+        let mut alice = open_user(&mut vault, Pubkey::default());
+        let mut bob = open_user(&mut vault, Pubkey::default());
+
+        vault.deposit(0, &mut alice, 100, 0).unwrap();
+        vault.deposit(50, &mut bob, 300, 0).unwrap();
+        assert_eq!(vault.total_staked_amount, 400);
+        assert_eq!(vault.total_user_count, 2);
+
+        // Alice has the first half to herself and a quarter of the second.
+        assert_eq!(vault.claim(100, &mut alice, 0).unwrap(), 625);
+        assert_eq!(vault.claim(100, &mut bob, 0).unwrap(), 375);
+        assert_eq!(vault.claim(200, &mut alice, 0).unwrap(), 0);
+
+        let reward = &vault.rewards[0];
+        assert_eq!(reward.reward_pool_amount, 0);
+        assert_eq!(reward.allocated_amount, 0);
+        assert_eq!(reward.owed_amount, 0);
+    }
+
+    #[test]
+    fn frequent_claims_do_not_lose_rounding_dust() {
+        let mut vault = funded_vault(Pubkey::new_unique(), Pubkey::new_unique(), 1_000, 0, 100);
+        let mut alice = open_user(&mut vault, Pubkey::default());
+        let mut bob = open_user(&mut vault, Pubkey::default());
+
+        vault.deposit(0, &mut alice, 1, 0).unwrap();
+        vault.deposit(0, &mut bob, 2, 0).unwrap();
+
+        // A third of 10 a second never makes a whole token on its own.
+        let claimed_amount: u64 = (1..=100)
+            .map(|now| vault.claim(now, &mut alice, 0).unwrap())
+            .sum();
+        assert_eq!(claimed_amount, 333);
+        assert_eq!(vault.claim(100, &mut bob, 0).unwrap(), 666);
+    }
+
+    #[test]
+    fn locked_stake_earns_a_boosted_share() {
+        let mut vault = funded_vault(Pubkey::new_unique(), Pubkey::new_unique(), 1_000, 0, 100);
+        let mut alice = open_user(&mut vault, Pubkey::default());
+        let mut bob = open_user(&mut vault, Pubkey::default());
+
+        vault.deposit(0, &mut alice, 100, 0).unwrap();
+        vault.deposit(0, &mut bob, 100, 4).unwrap();
+        assert_eq!(bob.weighted_amount, 300);
+        assert_eq!(bob.lock_end_time, 365 * 86400);
+        assert_eq!(vault.total_weighted_amount, 400);
+
+        assert_eq!(vault.claim(100, &mut alice, 0).unwrap(), 250);
+        assert_eq!(vault.claim(100, &mut bob, 0).unwrap(), 750);
+
+        assert_error(vault.unstake(100, &mut bob, 100, 0), CustomError::Locked);
+        assert_error(
+            vault.stake(100, &mut bob, 1, LOCK_TIERS.len() as u8),
+            CustomError::InvalidLockTier,
+        );
+    }
+
+    #[test]
+    fn stake_mint_only_changes_without_open_stake() {
+        let stake_mint = Pubkey::new_unique();
+        let mut vault = funded_vault(stake_mint, Pubkey::new_unique(), 1_000, 0, 100);
+        let mut alice = open_user(&mut vault, Pubkey::default());
+        assert!(vault.can_set_stake_token_mint(Pubkey::new_unique()));
+
+        vault.deposit(0, &mut alice, 100, 0).unwrap();
+        assert!(vault.can_set_stake_token_mint(stake_mint));
+        assert!(!vault.can_set_stake_token_mint(Pubkey::new_unique()));
+
+        vault.unstake(0, &mut alice, 100, 0).unwrap();
+        assert!(!vault.can_set_stake_token_mint(Pubkey::new_unique()));
+
+        vault.withdraw_unbonded(0, &mut alice).unwrap();
+        assert!(vault.can_set_stake_token_mint(Pubkey::new_unique()));
+    }
+
+    #[test]
+    fn staking_into_a_running_lock_relocks_the_position() {
+        let mut vault = funded_vault(Pubkey::new_unique(), Pubkey::new_unique(), 1_000, 0, 100);
+        let mut alice = open_user(&mut vault, Pubkey::default());
+
+        vault.deposit(0, &mut alice, 100, 1).unwrap();
+        vault.deposit(29 * 86400, &mut alice, 100, 0).unwrap();
+        assert_eq!(alice.lock_end_time, 59 * 86400);
+        assert_eq!(alice.weighted_amount, 250);
+        assert_error(
+            vault.unstake(30 * 86400, &mut alice, 200, 0),
+            CustomError::Locked,
+        );
+    }
+
+    #[test]
+    fn ended_lock_stops_boosting_without_a_keeper() {
+        let mut vault = funded_vault(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            4_500,
+            0,
+            60 * 86400,
+        );
+        let mut alice = open_user(&mut vault, Pubkey::default());
+        let mut bob = open_user(&mut vault, Pubkey::default());
+
+        vault.deposit(0, &mut alice, 100, 1).unwrap();
+        vault.deposit(0, &mut bob, 100, 0).unwrap();
+
+        // Boosted 5:4 for the first half, even for the second.
+        assert_eq!(
+            vault.claim(60 * 86400, &mut alice, 0).unwrap(),
+            1_250 + 1_125
+        );
+        assert_eq!(alice.weighted_amount, 100);
+        assert_eq!(vault.total_weighted_amount, 200);
+        assert_eq!(vault.claim(60 * 86400, &mut bob, 0).unwrap(), 1_000 + 1_125);
+    }
+
+    #[test]
+    fn unstaked_tokens_wait_out_the_unbonding_period() {
+        let mut vault = funded_vault(Pubkey::new_unique(), Pubkey::new_unique(), 1_000, 0, 100);
+        vault.unbonding_period = 10;
+        let mut alice = open_user(&mut vault, Pubkey::default());
+        let mut bob = open_user(&mut vault, Pubkey::default());
+
+        vault.deposit(0, &mut alice, 100, 0).unwrap();
+        vault.deposit(0, &mut bob, 100, 0).unwrap();
+        assert_eq!(vault.unstake(50, &mut alice, 100, 0).unwrap(), 0);
+        assert_eq!(alice.staked_amount, 0);
+        assert_eq!(alice.unbonding_amount, 100);
+        assert_eq!(alice.unbonding_end_time, 60);
+        assert_eq!(vault.total_staked_amount, 100);
+        assert_eq!(vault.total_unbonding_amount, 100);
+
+        assert_error(
+            vault.withdraw_unbonded(59, &mut alice),
+            CustomError::Unbonding,
+        );
+        assert_eq!(vault.withdraw_unbonded(60, &mut alice).unwrap(), 100);
+        assert_eq!(alice.unbonding_amount, 0);
+        assert_eq!(vault.total_unbonding_amount, 0);
+
+        // Unbonding tokens earn nothing, so Bob has the second half to himself.
+        assert_eq!(vault.claim(100, &mut alice, 0).unwrap(), 250);
+        assert_eq!(vault.claim(100, &mut bob, 0).unwrap(), 750);
+
+        assert_error(
+            vault.unstake(100, &mut alice, 1, 0),
+            CustomError::InsufficientStake,
+        );
+    }
+
+    #[test]
+    fn early_exit_penalty_goes_to_the_remaining_stakers() {
+        let stake_mint = Pubkey::new_unique();
+        let mut vault = funded_vault(stake_mint, stake_mint, 0, 0, 100);
+        vault.penalty_period = 100;
+        vault.penalty_bps = 1_000;
+        let mut alice = open_user(&mut vault, Pubkey::default());
+        let mut bob = open_user(&mut vault, Pubkey::default());
+
+        vault.deposit(0, &mut alice, 1_000, 0).unwrap();
+        vault.deposit(0, &mut bob, 1_000, 0).unwrap();
+
+        assert_eq!(vault.unstake(10, &mut alice, 1_000, 0).unwrap(), 100);
+        assert_eq!(alice.unbonding_amount, 900);
+        assert_eq!(vault.rewards[0].reward_pool_amount, 100);
+        assert_eq!(vault.claim(10, &mut bob, 0).unwrap(), 100);
+        assert_eq!(vault.claim(10, &mut alice, 0).unwrap(), 0);
+
+        // Outside the window exits are free.
+        assert_eq!(vault.unstake(100, &mut bob, 1_000, 0).unwrap(), 0);
+        assert_eq!(bob.unbonding_amount, 1_000);
+    }
+
+    #[test]
+    fn penalty_is_taken_after_the_unstake_fee() {
+        let stake_mint = Pubkey::new_unique();
+        let mut vault = funded_vault(stake_mint, stake_mint, 0, 0, 100);
+        vault.penalty_period = 100;
+        vault.penalty_bps = 10_000;
+        let mut alice = open_user(&mut vault, Pubkey::default());
+        let mut bob = open_user(&mut vault, Pubkey::default());
+
+        vault.deposit(0, &mut alice, 1_000, 0).unwrap();
+        vault.deposit(0, &mut bob, 1_000, 0).unwrap();
+
+        let fee_amount = bps_amount(1_000, 2_000);
+        assert_eq!(
+            vault.unstake(10, &mut alice, 1_000, fee_amount).unwrap(),
+            800
+        );
+        assert_eq!(alice.unbonding_amount, 0);
+    }
+
+    #[test]
+    fn burned_penalty_is_left_to_the_caller() {
+        let stake_mint = Pubkey::new_unique();
+        let mut vault = funded_vault(stake_mint, stake_mint, 0, 0, 100);
+        vault.penalty_period = 100;
+        vault.penalty_bps = 1_000;
+        vault.penalty_mode = PENALTY_BURN;
+        let mut alice = open_user(&mut vault, Pubkey::default());
+        let mut bob = open_user(&mut vault, Pubkey::default());
+
+        vault.deposit(0, &mut alice, 1_000, 0).unwrap();
+        vault.deposit(0, &mut bob, 1_000, 0).unwrap();
+
+        assert_eq!(vault.unstake(10, &mut alice, 1_000, 0).unwrap(), 100);
+        assert_eq!(vault.rewards[0].reward_pool_amount, 0);
+        assert_eq!(vault.claim(10, &mut bob, 0).unwrap(), 0);
+    }
+
+    #[test]
+    fn referrer_share_is_allocated_from_the_pool() {
+        let referrer = Pubkey::new_unique();
+        let mut vault = funded_vault(Pubkey::new_unique(), Pubkey::new_unique(), 1_000, 0, 100);
+        vault.referral_bps = 1_000;
+        let mut alice = open_user(&mut vault, referrer);
+        assert_eq!(alice.referrer, referrer);
+
+        vault.deposit(0, &mut alice, 100, 0).unwrap();
+        assert_eq!(vault.claim(50, &mut alice, 0).unwrap(), 500);
+        assert_eq!(vault.claim_referral(50, &mut alice, 0).unwrap(), 50);
+
+        let reward = &vault.rewards[0];
+        assert_eq!(reward.reward_pool_amount, 450);
+        assert_eq!(reward.allocated_amount, 0);
+
+        // A staker can not refer themselves.
+        let mut user = User::default();
+        let key = Pubkey::new_unique();
+        assert_error(
+            vault.open(&mut user, Pubkey::new_unique(), key, 255, key),
+            CustomError::InvalidReferrer,
+        );
+    }
+
+    #[test]
+    fn closing_moves_referral_rewards_out_of_the_position() {
+        let mut vault = funded_vault(Pubkey::new_unique(), Pubkey::new_unique(), 1_000, 0, 100);
+        vault.referral_bps = 1_000;
+        let mut alice = open_user(&mut vault, Pubkey::new_unique());
+
+        vault.deposit(0, &mut alice, 100, 0).unwrap();
+        assert_eq!(vault.unstake(50, &mut alice, 100, 0).unwrap(), 0);
+        assert_eq!(vault.withdraw_unbonded(50, &mut alice).unwrap(), 100);
+        assert_error(vault.close(50, &mut alice), CustomError::PositionNotEmpty);
+
+        assert_eq!(vault.claim(50, &mut alice, 0).unwrap(), 500);
+        assert_eq!(vault.close(50, &mut alice).unwrap(), [50, 0, 0, 0]);
+        assert_eq!(vault.total_user_count, 0);
+        assert_eq!(vault.rewards[0].owed_amount, 50);
+        assert!(!vault.is_empty());
+
+        let mut referrer = Referrer::default();
+        referrer.owed_amounts[0] = 50;
+        assert_eq!(vault.claim_owed_referral(&mut referrer, 0).unwrap(), 50);
+        assert!(vault.is_empty());
+    }
+
+    #[test]
+    fn emergency_withdraw_returns_stake_and_forfeits_rewards() {
+        let mut vault = funded_vault(Pubkey::new_unique(), Pubkey::new_unique(), 1_000, 0, 100);
+        vault.unbonding_period = 100;
+        let mut alice = open_user(&mut vault, Pubkey::default());
+        let mut bob = open_user(&mut vault, Pubkey::default());
+
+        vault.deposit(0, &mut alice, 100, 4).unwrap();
+        vault.deposit(0, &mut bob, 100, 0).unwrap();
+        vault.unstake(50, &mut bob, 40, 0).unwrap();
+        // Settles Alice's share of the first half.
+        vault.update(50).unwrap();
+        vault.update_user(&mut alice).unwrap();
+        let earned_amount = alice.rewards[0].earned_amount;
+        assert_eq!(earned_amount, 375);
+
+        // Locks and unbonding are skipped.
+        assert_eq!(vault.emergency_withdraw(&mut alice).unwrap(), 100);
+        assert_eq!(vault.emergency_withdraw(&mut bob).unwrap(), 100);
+        assert_eq!(alice.staked_amount, 0);
+        assert_eq!(alice.lock_end_time, 0);
+        assert_eq!(alice.rewards[0], UserReward::default());
+        assert_eq!(bob.unbonding_amount, 0);
+        assert_eq!(vault.total_staked_amount, 0);
+        assert_eq!(vault.total_weighted_amount, 0);
+        assert_eq!(vault.total_unbonding_amount, 0);
+
+        // Alice's settled rewards go back to the pool.
+        assert_eq!(vault.rewards[0].owed_amount, 0);
+        vault.update(100).unwrap();
+        assert_eq!(vault.rewards[0].unallocated_amount().unwrap(), 1_000);
+    }
+
+    #[test]
+    fn compound_restakes_stake_mint_rewards_less_the_fee() {
+        let stake_mint = Pubkey::new_unique();
+        let mut vault = funded_vault(stake_mint, stake_mint, 1_000, 0, 100);
+        let mut alice = open_user(&mut vault, Pubkey::default());
+
+        vault.deposit(0, &mut alice, 100, 0).unwrap();
+        assert_eq!(vault.compound(50, &mut alice, 1_000).unwrap(), (450, 50));
+        assert_eq!(alice.staked_amount, 550);
+        assert_eq!(alice.rewards[0].earned_amount, 0);
+        assert_eq!(vault.total_staked_amount, 550);
+        assert_eq!(vault.rewards[0].reward_pool_amount, 500);
+    }
+
+    fn legacy_vault_data(legacy: &LegacyVault, users: &[LegacyUser]) -> Vec<u8> {
+        let mut data = vec![0; LegacyVault::LEN];
+        let header = [
+            legacy.authority.to_bytes().to_vec(),
+            legacy.stake_token_mint.to_bytes().to_vec(),
+            legacy.reward_pool_amount.to_le_bytes().to_vec(),
+            legacy.total_staked_amount.to_le_bytes().to_vec(),
+            legacy.daily_payout_amount.to_le_bytes().to_vec(),
+            legacy.last_updated_time.to_le_bytes().to_vec(),
+        ]
+        .concat();
+        data[8..8 + header.len()].copy_from_slice(&header);
+        for (index, user) in users.iter().enumerate() {
+            LegacyVault::set_user(&mut data, index, user).unwrap();
+        }
+        data[LegacyVault::USER_COUNT_OFFSET..LegacyVault::USER_COUNT_OFFSET + 2]
+            .copy_from_slice(&(users.len() as u16).to_le_bytes());
+        data[LegacyVault::USER_COUNT_OFFSET + 2] = 254;
+        data
+    }
+
+    #[test]
+    fn legacy_vault_is_read_in_place() {
+        let legacy = LegacyVault {
+            authority: Pubkey::new_unique(),
+            stake_token_mint: Pubkey::new_unique(),
+            reward_pool_amount: 10_000,
+            total_staked_amount: 300,
+            daily_payout_amount: 864,
+            last_updated_time: 1_000,
+        };
+        let alice = Pubkey::new_unique();
+        let bob = Pubkey::new_unique();
+        let users = [
+            LegacyUser {
+                key: alice,
+                staked_amount: 100,
+                earned_amount: 5,
+            },
+            LegacyUser {
+                key: bob,
+                staked_amount: 200,
+                earned_amount: 0,
+            },
+        ];
+        let mut data = legacy_vault_data(&legacy, &users);
+
+        let header = LegacyVault::header(&data).unwrap();
+        assert_eq!(header.authority, legacy.authority);
+        assert_eq!(header.last_updated_time, 1_000);
+        assert_eq!(LegacyVault::user_count(&data), 2);
+        assert_eq!(LegacyVault::bump(&data), 254);
+        assert_eq!(LegacyVault::user(&data, 1).unwrap(), users[1]);
+        assert_error(
+            LegacyVault::user(&data, 2),
+            CustomError::LegacyStakerNotFound,
+        );
+
+        // 864 a day is 1 per 100 seconds, shared 1:2.
+        assert_eq!(header.settle(&mut data, 31_000).unwrap(), 305);
+        assert_eq!(LegacyVault::user(&data, 0).unwrap().earned_amount, 105);
+        assert_eq!(LegacyVault::user(&data, 1).unwrap().earned_amount, 200);
+    }
+
+    #[test]
+    fn migrated_stakers_keep_earning_until_they_claim_their_position() {
+        let stake_mint = Pubkey::new_unique();
+        let legacy = LegacyVault {
+            authority: Pubkey::new_unique(),
+            stake_token_mint: stake_mint,
+            reward_pool_amount: 1_305,
+            total_staked_amount: 300,
+            daily_payout_amount: 864,
+            last_updated_time: 1_000,
+        };
+        let mut vault = Vault::default();
+        vault.migrate(1_000, &legacy, 305).unwrap();
+        assert_eq!(vault.total_staked_amount, 300);
+        assert_eq!(vault.total_weighted_amount, 300);
+
+        // The leftover pool pays out at the legacy rate.
+        let reward = vault.rewards[0];
+        assert_eq!(reward.mint, stake_mint);
+        assert_eq!(reward.reward_pool_amount, 1_305);
+        assert_eq!(reward.owed_amount, 305);
+        assert_eq!(reward.period_amount, 1_000);
+        assert_eq!(reward.period_end_time, 101_000);
+
+        // Half the period later a third of the emission is Alice's.
+        let mut alice = open_user(&mut vault, Pubkey::default());
+        vault.migrate_stake(51_000, &mut alice, 100, 105).unwrap();
+        assert_eq!(alice.staked_amount, 100);
+        assert_eq!(alice.rewards[0].earned_amount, 105 + 166);
+        assert_eq!(vault.total_staked_amount, 300);
+        assert_eq!(vault.total_weighted_amount, 300);
+
+        assert_eq!(vault.claim(101_000, &mut alice, 0).unwrap(), 271 + 167);
+        let mut bob = open_user(&mut vault, Pubkey::default());
+        vault.migrate_stake(101_000, &mut bob, 200, 200).unwrap();
+        assert_eq!(vault.claim(101_000, &mut bob, 0).unwrap(), 200 + 666);
+
+        assert_error(
+            Vault::default().migrate(1_000, &legacy, 1_306),
+            CustomError::InsufficientRewards,
+        );
+    }
+}